@@ -0,0 +1,94 @@
+use anyhow::Result;
+use chrono::prelude::*;
+use clap::ValueEnum;
+use reqwest::{Client, Url};
+use serde::{Deserialize, Serialize};
+
+use crate::graphql_url;
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthMode {
+    /// Personal access tokens from --token, GITHUB_TOKENS or GITHUB_TOKEN
+    Token,
+    /// A GitHub App installation token, minted at startup
+    App,
+}
+
+// Claims GitHub expects in the JWT an app signs to identify itself.
+#[derive(Serialize)]
+struct AppClaims {
+    iat: i64,
+    exp: i64,
+    iss: String,
+}
+
+#[derive(Deserialize)]
+struct InstallationToken {
+    token: String,
+}
+
+// A JWT for the app, signed with its private key (PEM). Issued a minute in
+// the past to allow for clock drift, and valid for ten minutes, the most
+// GitHub accepts.
+pub fn app_jwt(app_id: &str, private_key: &[u8]) -> Result<String> {
+    let now = Utc::now().timestamp();
+    let claims = AppClaims {
+        iat: now - 60,
+        exp: now + 600,
+        iss: app_id.to_string(),
+    };
+    let key = jsonwebtoken::EncodingKey::from_rsa_pem(private_key)
+        .map_err(|e| anyhow::anyhow!("invalid GitHub App private key: {}", e))?;
+    let header = jsonwebtoken::Header::new(jsonwebtoken::Algorithm::RS256);
+    Ok(jsonwebtoken::encode(&header, &claims, &key)?)
+}
+
+// The REST root next to a GraphQL endpoint: https://api.github.com for
+// github.com, https://<host>/api/v3 for GitHub Enterprise Server.
+fn rest_url(api_url: &str) -> Result<Url> {
+    let mut url = graphql_url(api_url)?;
+    let path = url.path().trim_end_matches('/').to_string();
+    match path.strip_suffix("/graphql") {
+        Some("") => url.set_path("/"),
+        Some(prefix) => url.set_path(&format!("{}/v3", prefix)),
+        None => return Err(anyhow::anyhow!("can't derive the REST API root from {}", api_url)),
+    }
+    Ok(url)
+}
+
+// Exchanges the app's JWT for an installation access token. The token is
+// valid for an hour and is used like any other bearer token.
+pub async fn installation_token(
+    api_url: &str,
+    app_id: &str,
+    installation_id: u64,
+    private_key: &[u8],
+    client: &Client,
+    user_agent: &str,
+) -> Result<String> {
+    let jwt = app_jwt(app_id, private_key)?;
+    let mut url = rest_url(api_url)?;
+    url.path_segments_mut()
+        .map_err(|_| anyhow::anyhow!("invalid API URL {}", api_url))?
+        .pop_if_empty()
+        .extend(["app", "installations", &installation_id.to_string(), "access_tokens"]);
+    let response = client
+        .post(url)
+        .header("Authorization", format!("Bearer {}", jwt))
+        .header("Accept", "application/vnd.github+json")
+        .header("User-Agent", user_agent)
+        .send()
+        .await?;
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        return Err(anyhow::anyhow!(
+            "couldn't mint a token for installation {}: HTTP {}: {}",
+            installation_id,
+            status,
+            body
+        ));
+    }
+    Ok(response.json::<InstallationToken>().await?.token)
+}
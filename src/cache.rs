@@ -0,0 +1,128 @@
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::write_private;
+
+// GraphQL response bodies on disk, one file per request. Files older than
+// the TTL are treated as missing and overwritten by the next fetch.
+pub struct ResponseCache {
+    dir: PathBuf,
+    ttl: Duration,
+}
+
+impl ResponseCache {
+    pub fn new(dir: &Path, ttl: Duration) -> ResponseCache {
+        ResponseCache {
+            dir: dir.to_path_buf(),
+            ttl,
+        }
+    }
+
+    // $XDG_CACHE_HOME/git-stats, falling back to ~/.cache/git-stats.
+    pub fn default_dir() -> Option<PathBuf> {
+        let base = match std::env::var_os("XDG_CACHE_HOME").filter(|dir| !dir.is_empty()) {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(std::env::var_os("HOME")?).join(".cache"),
+        };
+        Some(base.join("git-stats"))
+    }
+
+    // The key covers the credentials as well as the request, so data one
+    // token can see is never served to another.
+    pub fn key(credentials: &str, url: &str, query: &str) -> String {
+        let mut hasher = Sha256::new();
+        for part in [credentials, url, query] {
+            hasher.update(part.as_bytes());
+            hasher.update([0]);
+        }
+        format!("{:x}", hasher.finalize())
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        let path = self.path(key);
+        let age = fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())?;
+        if age > self.ttl {
+            return None;
+        }
+        fs::read_to_string(path).ok()
+    }
+
+    // Only complete answers are kept: a body carrying GraphQL errors (rate
+    // limits included) is always fetched again.
+    pub fn put(&self, key: &str, body: &str) -> Result<()> {
+        let cacheable = serde_json::from_str::<serde_json::Value>(body)
+            .is_ok_and(|value| value.get("errors").is_none());
+        if cacheable {
+            create_private_dir(&self.dir)?;
+            write_private(&self.path(key), body.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+// Responses can hold anything the token can read, so the directories
+// created for them are only open to the current user. An existing directory
+// is left as it is.
+fn create_private_dir(dir: &Path) -> Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(dir)?;
+    Ok(())
+}
+
+// The resolved repository names of each owner, so that runs against the
+// same owner skip listing them again. Kept in memory for the life of the
+// Fetcher and, given a directory, on disk across runs. Unlike the response
+// cache, it holds the final list rather than each page of it, and has its
+// own TTL.
+pub struct RepositoryListCache {
+    ttl: Duration,
+    disk: Option<ResponseCache>,
+    memory: Mutex<HashMap<String, (Instant, Vec<String>)>>,
+}
+
+impl RepositoryListCache {
+    pub fn new(ttl: Duration, dir: Option<&Path>) -> RepositoryListCache {
+        RepositoryListCache {
+            ttl,
+            disk: dir.map(|dir| ResponseCache::new(dir, ttl)),
+            memory: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<Vec<String>> {
+        if let Some((stored, repositories)) = self.memory.lock().unwrap().get(key) {
+            if stored.elapsed() <= self.ttl {
+                return Some(repositories.clone());
+            }
+        }
+        serde_json::from_str(&self.disk.as_ref()?.get(key)?).ok()
+    }
+
+    pub fn put(&self, key: &str, repositories: &[String]) -> Result<()> {
+        self.memory
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), (Instant::now(), repositories.to_vec()));
+        if let Some(disk) = &self.disk {
+            disk.put(key, &serde_json::to_string(repositories)?)?;
+        }
+        Ok(())
+    }
+}
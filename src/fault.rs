@@ -0,0 +1,121 @@
+use futures::future::BoxFuture;
+use reqwest::{
+    header::{HeaderMap, HeaderValue},
+    StatusCode,
+};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::transport::{GraphQlRequest, HttpResponse, Transport, TransportError};
+
+// The ways FaultyTransport makes a request fail, each one GitHub or the
+// network really produces.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fault {
+    // 500 Internal Server Error.
+    ServerError,
+    // 403 for the secondary ("abuse") rate limit, with a Retry-After.
+    AbuseLimit,
+    // No response within the client's timeout.
+    Timeout,
+    // A 200 whose body was cut off halfway.
+    MalformedBody,
+}
+
+const FAULTS: [Fault; 4] = [Fault::ServerError, Fault::AbuseLimit, Fault::Timeout, Fault::MalformedBody];
+
+// Wraps a transport and fails a share of its requests, for testing the
+// retry machinery end to end. The faults come from a seeded generator, so a
+// failing run can be repeated.
+pub struct FaultyTransport {
+    inner: Box<dyn Transport>,
+    // Share of requests that fail, from 0 to 1.
+    rate: f64,
+    // Seconds the abuse responses ask to wait.
+    retry_after: u64,
+    state: AtomicU64,
+    injected: AtomicU64,
+}
+
+impl FaultyTransport {
+    pub fn new(inner: Box<dyn Transport>, rate: f64, seed: u64) -> FaultyTransport {
+        FaultyTransport {
+            inner,
+            rate: rate.clamp(0.0, 1.0),
+            retry_after: 0,
+            // xorshift never leaves 0, so it can't start there.
+            state: AtomicU64::new(seed | 1),
+            injected: AtomicU64::new(0),
+        }
+    }
+
+    pub fn with_retry_after(mut self, seconds: u64) -> FaultyTransport {
+        self.retry_after = seconds;
+        self
+    }
+
+    // How many requests have failed on purpose so far.
+    pub fn injected(&self) -> u64 {
+        self.injected.load(Ordering::Relaxed)
+    }
+
+    // xorshift64*: plenty for picking faults.
+    fn next(&self) -> u64 {
+        let step = |mut x: u64| {
+            x ^= x >> 12;
+            x ^= x << 25;
+            x ^= x >> 27;
+            x
+        };
+        let previous = self
+            .state
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |x| Some(step(x)))
+            .unwrap_or_default();
+        step(previous).wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn pick(&self) -> Option<Fault> {
+        let roll = (self.next() >> 11) as f64 / (1u64 << 53) as f64;
+        if roll >= self.rate {
+            return None;
+        }
+        self.injected.fetch_add(1, Ordering::Relaxed);
+        Some(FAULTS[(self.next() % FAULTS.len() as u64) as usize])
+    }
+}
+
+impl Transport for FaultyTransport {
+    fn post<'a>(&'a self, request: GraphQlRequest<'a>) -> BoxFuture<'a, Result<HttpResponse, TransportError>> {
+        Box::pin(async move {
+            let fault = self.pick();
+            if let Some(fault) = fault {
+                tracing::debug!("Injecting {:?}", fault);
+            }
+            match fault {
+                None => self.inner.post(request).await,
+                Some(Fault::ServerError) => Ok(HttpResponse {
+                    status: StatusCode::INTERNAL_SERVER_ERROR,
+                    headers: HeaderMap::new(),
+                    body: String::new(),
+                }),
+                Some(Fault::AbuseLimit) => {
+                    let mut headers = HeaderMap::new();
+                    headers.insert("retry-after", HeaderValue::from(self.retry_after));
+                    Ok(HttpResponse {
+                        status: StatusCode::FORBIDDEN,
+                        headers,
+                        body: r#"{"message":"You have exceeded a secondary rate limit. Please wait a few minutes before you try again."}"#
+                            .to_string(),
+                    })
+                }
+                Some(Fault::Timeout) => Err(TransportError::Timeout),
+                Some(Fault::MalformedBody) => {
+                    let mut response = self.inner.post(request).await?;
+                    let half = response.body.len() / 2;
+                    let cut = (0..=half).rev().find(|&i| response.body.is_char_boundary(i)).unwrap_or(0);
+                    response.body.truncate(cut);
+                    Ok(response)
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,5103 @@
+use anyhow::Result;
+use chrono::prelude::*;
+use clap::ValueEnum;
+use futures::{channel::mpsc, StreamExt};
+use indicatif::{ProgressBar, ProgressStyle};
+use reqwest::Client;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::{Display, Formatter, Write},
+    ffi::OsString,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+pub mod auth;
+pub mod cache;
+#[cfg(feature = "fault-injection")]
+pub mod fault;
+pub mod transport;
+
+use cache::{RepositoryListCache, ResponseCache};
+use transport::{GraphQlRequest, HttpResponse, Transport, TransportError};
+
+// Keyed by lowercased login, since GitHub logins are case-insensitive and
+// older data can spell the same account differently. Each entry keeps the
+// spelling first seen for display.
+pub struct GitHubUsers(pub HashMap<String, UserStats>);
+
+// Accounts left out of the statistics entirely.
+#[derive(Debug, Default)]
+pub struct UserFilter {
+    pub bots: bool,
+    pub users: HashSet<String>,
+    // With --team, the lowercased logins of its members; everyone else is
+    // left out.
+    pub team: Option<HashSet<String>>,
+    // Keep reviews by non-members on pull requests a member authored.
+    pub outside_reviewers: bool,
+    // With --max-pr-age-days, pull requests opened before this are left out,
+    // whatever the date window.
+    pub opened_since: Option<NaiveDate>,
+}
+
+impl UserFilter {
+    // Pull requests by bots or --exclude-user accounts are left out whole,
+    // reviews included, so they don't count towards the summary or the
+    // average LOC per PR either. Team filtering stays per user: members'
+    // reviews on outsiders' pull requests still count.
+    pub fn excludes_pull_request(&self, pr: &PullRequest) -> bool {
+        self.excludes_account(&pr.author.login)
+            || self.opened_since.is_some_and(|since| pr.created_at.date_naive() < since)
+    }
+
+    pub fn excludes(&self, login: &str) -> bool {
+        self.excludes_account(login) || !self.in_team(login)
+    }
+
+    // For reviews, review comments and PR comments on a pull request by
+    // `author`.
+    pub fn excludes_reviewer(&self, login: &str, author: &str) -> bool {
+        if self.outside_reviewers && self.in_team(author) {
+            return self.excludes_account(login);
+        }
+        self.excludes(login)
+    }
+
+    fn excludes_account(&self, login: &str) -> bool {
+        (self.bots && login.ends_with("[bot]"))
+            || self.users.iter().any(|user| user.eq_ignore_ascii_case(login))
+    }
+
+    fn in_team(&self, login: &str) -> bool {
+        self.team
+            .as_ref()
+            .is_none_or(|team| team.contains(&login.to_lowercase()))
+    }
+}
+
+pub struct ScoredUser(pub Vec<(String, UserStats)>);
+
+impl GitHubUsers {
+    pub fn take_unattributable(&mut self) -> Option<UserStats> {
+        self.0.remove(DELETED_LOGIN)
+    }
+
+    // None for filtered accounts, so they never get an entry.
+    fn user(&mut self, login: &str, filter: &UserFilter) -> Option<&mut UserStats> {
+        if filter.excludes(login) {
+            return None;
+        }
+        Some(self.entry(login))
+    }
+
+    // Like user, for review activity on a pull request by `author`.
+    fn reviewer(&mut self, login: &str, author: &str, filter: &UserFilter) -> Option<&mut UserStats> {
+        if filter.excludes_reviewer(login, author) {
+            return None;
+        }
+        Some(self.entry(login))
+    }
+
+    fn entry(&mut self, login: &str) -> &mut UserStats {
+        self.0.entry(login.to_lowercase()).or_insert_with(|| UserStats {
+            login: login.to_string(),
+            ..UserStats::new()
+        })
+    }
+
+    pub fn add_pull_request(&mut self, pr: &PullRequest, filter: &UserFilter, latest_review_only: bool) {
+        if filter.excludes_pull_request(pr) {
+            return;
+        }
+        // Every contributor is credited with the pull request, and its size
+        // is split between them. The first one keeps what doesn't divide.
+        let contributors: Vec<String> = pr
+            .contributors()
+            .into_iter()
+            .filter(|login| !filter.excludes(login))
+            .collect();
+        let count = contributors.len() as u64;
+        for (i, login) in contributors.iter().enumerate() {
+            let share = |total: u64| total / count + if i == 0 { total % count } else { 0 };
+            let Some(stats) = self.user(login, filter) else {
+                continue;
+            };
+            let size = PullRequestSize {
+                additions: share(pr.additions),
+                deletions: share(pr.deletions),
+                changed_files: share(pr.changed_files),
+                date: pr.activity_date(),
+            };
+            stats.additions += size.additions;
+            stats.deletions += size.deletions;
+            stats.changed_files += size.changed_files;
+            stats.pull_requests += 1;
+            stats.pull_request_sizes.push(size);
+        }
+        if pr.is_self_merge() {
+            if let Some(stats) = self.user(&pr.author.login, filter) {
+                stats.self_merges += 1;
+            }
+        }
+        // Review credit is gathered per login first, so each user keeps it
+        // together with the size of the pull request it was earned on.
+        let author = pr.author.login.as_str();
+        let blank = ReviewCredit {
+            lines: pr.additions + pr.deletions,
+            date: pr.activity_date(),
+            ..ReviewCredit::default()
+        };
+        let mut credits: Vec<(&str, ReviewCredit)> = Vec::new();
+        for review in pr.reviews.nodes.iter() {
+            let credit = review_credit(&mut credits, &review.author.login, &blank);
+            credit.reviewed = true;
+            credit.comment_words += word_count(&review.body_text);
+            credit.review_comments += review.comments.total_count;
+        }
+        for review in pr.tallied_reviews(latest_review_only) {
+            let credit = review_credit(&mut credits, &review.author.login, &blank);
+            if review.state == "APPROVED" {
+                credit.approvals += 1;
+            } else if review.state == "COMMENTED" {
+                credit.comments += 1;
+            } else if review.state == "CHANGES_REQUESTED" {
+                credit.requested_changes += 1;
+            }
+        }
+
+        for comment in pr.comments.nodes.iter() {
+            let credit = review_credit(&mut credits, &comment.author.login, &blank);
+            credit.comment_words += word_count(&comment.body_text);
+            credit.comments += 1;
+        }
+
+        if let Some(reviewer) = pr.unblocking_reviewer() {
+            review_credit(&mut credits, reviewer, &blank).unblocking_approvals += 1;
+        }
+
+        for (login, credit) in credits {
+            let Some(stats) = self.reviewer(login, author, filter) else {
+                continue;
+            };
+            stats.approvals += credit.approvals;
+            stats.comments += credit.comments;
+            stats.review_comments += credit.review_comments;
+            stats.requested_changes += credit.requested_changes;
+            stats.unblocking_approvals += credit.unblocking_approvals;
+            stats.comment_words += credit.comment_words;
+            stats.prs_reviewed += credit.reviewed as u64;
+            stats.review_credits.push(credit);
+        }
+
+        for event in pr.timeline_items.nodes.iter() {
+            if event.pull_request_commit.is_none() {
+                continue;
+            }
+            let Some(review) = &event.review else {
+                continue;
+            };
+            if let Some(stats) = self.reviewer(&review.author.login, author, filter) {
+                stats.reviews_dismissed += 1;
+            }
+        }
+    }
+
+    // Adds the counts of a previous run. Its scores are dropped, since
+    // finalize scores the combined counts.
+    pub fn add_prior(&mut self, prior: &PriorReport, filter: &UserFilter) {
+        for (login, counts) in prior.users() {
+            if let Some(stats) = self.user(login, filter) {
+                stats.add_counts(counts);
+            }
+        }
+        if let Some(unattributable) = &prior.unattributable {
+            if let Some(stats) = self.user(DELETED_LOGIN, filter) {
+                stats.pull_requests += unattributable.pull_requests;
+                stats.approvals += unattributable.approvals;
+                stats.comments += unattributable.comments;
+                stats.review_comments += unattributable.review_comments;
+                stats.requested_changes += unattributable.requested_changes;
+                stats.additions += unattributable.additions;
+                stats.deletions += unattributable.deletions;
+            }
+        }
+    }
+
+    pub fn finalize(
+        &mut self,
+        weight: &f64,
+        reviewer_weights: &HashMap<String, f64>,
+        weights: &ScoringWeights,
+    ) -> ScoredUser {
+        let weight = *weight;
+        let reviewer_weights: HashMap<String, f64> = reviewer_weights
+            .iter()
+            .map(|(login, factor)| (login.to_lowercase(), *factor))
+            .collect();
+        let now = Utc::now();
+        let decay = |date: DateTime<Utc>| weights.decay(date, now);
+        let mut v = Vec::new();
+        // Reviewing, authorship and lines points per user, for balanced mode.
+        let mut dimensions = Vec::new();
+        for (user, stats) in self.0.iter() {
+            let mut stats = stats.clone();
+            let reviewer_weight = reviewer_weights.get(user).copied().unwrap_or(1.0);
+            let mut breakdown = ScoreBreakdown::default();
+            if weights.size_weighted_reviews || weights.decay_half_life.is_some() {
+                for credit in stats.review_credits.iter() {
+                    let size_factor = if weights.size_weighted_reviews {
+                        review_size_factor(credit.lines, weight)
+                    } else {
+                        1.0
+                    };
+                    breakdown.add_review(credit, weights, weight * reviewer_weight * size_factor * decay(credit.date));
+                }
+            } else {
+                let totals = ReviewCredit {
+                    approvals: stats.approvals,
+                    comments: stats.comments,
+                    review_comments: stats.review_comments,
+                    requested_changes: stats.requested_changes,
+                    unblocking_approvals: stats.unblocking_approvals,
+                    ..ReviewCredit::default()
+                };
+                breakdown.add_review(&totals, weights, weight * reviewer_weight);
+            }
+            match weights.size_scoring {
+                SizeScoring::Linear if weights.decay_half_life.is_none() => {
+                    breakdown.additions = stats.additions as f64 * weights.addition;
+                    breakdown.deletions = stats.deletions as f64 * weights.deletion * weight;
+                }
+                SizeScoring::Linear | SizeScoring::Log => {
+                    for size in stats.pull_request_sizes.iter() {
+                        let (additions, deletions) = match weights.size_scoring {
+                            SizeScoring::Log => (log_lines(size.additions, weight), log_lines(size.deletions, weight)),
+                            _ => (size.additions as f64, size.deletions as f64),
+                        };
+                        breakdown.additions += additions * weights.addition * decay(size.date);
+                        breakdown.deletions += deletions * weights.deletion * weight * decay(size.date);
+                    }
+                }
+                // A bucket doesn't tell added lines from deleted ones, so its
+                // points all count as additions.
+                SizeScoring::Bucket => {
+                    let points: f64 = stats
+                        .pull_request_sizes
+                        .iter()
+                        .map(|size| size.bucket() as f64 * decay(size.date))
+                        .sum();
+                    breakdown.additions = points * weights.addition * weight;
+                }
+            }
+            breakdown.comment_words = if weights.decay_half_life.is_some() {
+                stats
+                    .review_credits
+                    .iter()
+                    .map(|credit| credit.comment_words as f64 * weights.comment_word * decay(credit.date))
+                    .sum()
+            } else {
+                stats.comment_words as f64 * weights.comment_word
+            };
+            let score = breakdown.total();
+            let authored = if weights.decay_half_life.is_some() {
+                stats.pull_request_sizes.iter().map(|size| decay(size.date)).sum()
+            } else {
+                stats.pull_requests as f64
+            };
+            dimensions.push([breakdown.reviewing(), authored, breakdown.additions + breakdown.deletions]);
+            stats.breakdown = breakdown;
+            stats.score = score;
+            // Reviewers who authored nothing have no average rather than 0.
+            stats.avg_changed_files_per_pr = if stats.pull_requests > 0 {
+                Some(stats.changed_files as f64 / stats.pull_requests as f64)
+            } else {
+                None
+            };
+            // Nor a review ratio, which would be a division by zero.
+            stats.review_ratio = if stats.pull_requests > 0 {
+                Some(stats.reviews() as f64 / stats.pull_requests as f64)
+            } else {
+                None
+            };
+            v.push((stats.login.clone(), stats.clone()));
+        }
+        // Balanced mode scores each user by the mean of their z-scores less
+        // how much those differ, so an even profile beats a lopsided one with
+        // the same mean. It needs the whole cohort, hence a second pass.
+        if weights.score_mode == ScoreMode::Balanced {
+            for ((_, stats), z) in v.iter_mut().zip(z_scores(&dimensions)) {
+                let (mean, spread) = mean_and_spread(&z);
+                stats.score = mean - spread;
+            }
+        }
+        // Ties are broken by pull requests, then login, so the order doesn't
+        // depend on HashMap iteration and reruns diff cleanly.
+        v.sort_by(|a, b| {
+            b.1.score
+                .total_cmp(&a.1.score)
+                .then(b.1.pull_requests.cmp(&a.1.pull_requests))
+                .then(a.0.cmp(&b.0))
+        });
+        ScoredUser(v)
+    }
+}
+
+// A requested-changes review is worth this many approvals.
+const REQUESTED_CHANGES_MULTIPLIER: f64 = 2.0;
+// A deleted line is worth an added one divided by this, before the
+// average-LOC scaling deletions get.
+const DELETION_WEIGHT_DIVISOR: f64 = 10.0;
+
+// The default weights, shared by the CLI flags and ScoringWeights::default.
+// Inline review comments earn nothing unless asked for, as before they were
+// counted.
+pub const DEFAULT_APPROVAL_WEIGHT: f64 = 1.0;
+pub const DEFAULT_COMMENT_WEIGHT: f64 = 1.0;
+pub const DEFAULT_REVIEW_COMMENT_WEIGHT: f64 = 0.0;
+pub const DEFAULT_REQUESTED_CHANGES_WEIGHT: f64 = REQUESTED_CHANGES_MULTIPLIER * DEFAULT_APPROVAL_WEIGHT;
+pub const DEFAULT_ADDITION_WEIGHT: f64 = 1.0;
+pub const DEFAULT_DELETION_WEIGHT: f64 = DEFAULT_ADDITION_WEIGHT / DELETION_WEIGHT_DIVISOR;
+// Extra approvals' worth per unblocking approval, when that credit is on.
+pub const DEFAULT_UNBLOCKING_WEIGHT: f64 = 1.0;
+
+// Points per unit of each contribution. Review points and deletions are also
+// multiplied by the average LOC per PR.
+pub struct ScoringWeights {
+    pub approval: f64,
+    pub comment: f64,
+    pub review_comment: f64,
+    pub requested_changes: f64,
+    pub unblocking: f64,
+    pub addition: f64,
+    pub deletion: f64,
+    pub comment_word: f64,
+    pub size_scoring: SizeScoring,
+    // Scale each review's points by the size of the pull request reviewed.
+    pub size_weighted_reviews: bool,
+    // Halve what a pull request and its reviews are worth every this many
+    // days since it was merged. None counts everything in full.
+    pub decay_half_life: Option<f64>,
+    pub score_mode: ScoreMode,
+}
+
+// The CLI's scoring with no flags given: linear sizes, no unblocking credit,
+// no comment words and no decay.
+impl Default for ScoringWeights {
+    fn default() -> ScoringWeights {
+        ScoringWeights {
+            approval: DEFAULT_APPROVAL_WEIGHT,
+            comment: DEFAULT_COMMENT_WEIGHT,
+            review_comment: DEFAULT_REVIEW_COMMENT_WEIGHT,
+            requested_changes: DEFAULT_REQUESTED_CHANGES_WEIGHT,
+            unblocking: 0.0,
+            addition: DEFAULT_ADDITION_WEIGHT,
+            deletion: DEFAULT_DELETION_WEIGHT,
+            comment_word: 0.0,
+            size_scoring: SizeScoring::Linear,
+            size_weighted_reviews: false,
+            decay_half_life: None,
+            score_mode: ScoreMode::Additive,
+        }
+    }
+}
+
+impl ScoringWeights {
+    // 0.5^(age / half-life), with the age in fractional days. Dates in the
+    // future count in full.
+    fn decay(&self, date: DateTime<Utc>, now: DateTime<Utc>) -> f64 {
+        let Some(half_life) = self.decay_half_life else {
+            return 1.0;
+        };
+        let age_days = (now - date).num_seconds().max(0) as f64 / 86_400.0;
+        0.5f64.powf(age_days / half_life)
+    }
+}
+
+// One user's review credit on one pull request, with that pull request's
+// size in lines added and deleted and its date.
+#[derive(Debug, Default, Clone)]
+pub struct ReviewCredit {
+    pub lines: u64,
+    pub date: DateTime<Utc>,
+    pub approvals: u64,
+    pub comments: u64,
+    pub review_comments: u64,
+    pub requested_changes: u64,
+    pub unblocking_approvals: u64,
+    pub comment_words: u64,
+    // Whether they submitted a review, of any state, rather than only
+    // commenting on the conversation.
+    pub reviewed: bool,
+}
+
+// The points each kind of contribution adds to a user's score, after every
+// multiplier. They sum to the score.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct ScoreBreakdown {
+    pub approvals: f64,
+    pub comments: f64,
+    pub review_comments: f64,
+    pub requested_changes: f64,
+    pub unblocking_approvals: f64,
+    pub additions: f64,
+    pub deletions: f64,
+    pub comment_words: f64,
+}
+
+impl ScoreBreakdown {
+    // `factor` is everything a review's points are multiplied by: the
+    // average LOC per PR, the reviewer's weight, and the size and decay
+    // factors when those are on.
+    fn add_review(&mut self, credit: &ReviewCredit, weights: &ScoringWeights, factor: f64) {
+        self.approvals += credit.approvals as f64 * weights.approval * factor;
+        self.comments += credit.comments as f64 * weights.comment * factor;
+        self.review_comments += credit.review_comments as f64 * weights.review_comment * factor;
+        self.requested_changes += credit.requested_changes as f64 * weights.requested_changes * factor;
+        self.unblocking_approvals += credit.unblocking_approvals as f64 * weights.unblocking * factor;
+    }
+
+    fn parts(&self) -> [(&'static str, f64); 8] {
+        [
+            ("approvals", self.approvals),
+            ("comments", self.comments),
+            ("review_comments", self.review_comments),
+            ("requested_changes", self.requested_changes),
+            ("unblocking_approvals", self.unblocking_approvals),
+            ("additions", self.additions),
+            ("deletions", self.deletions),
+            ("comment_words", self.comment_words),
+        ]
+    }
+
+    fn total(&self) -> f64 {
+        self.parts().iter().map(|(_, points)| points).sum()
+    }
+
+    // Everything but the authored lines.
+    fn reviewing(&self) -> f64 {
+        self.total() - self.additions - self.deletions
+    }
+}
+
+fn review_credit<'a, 'b>(
+    credits: &'b mut Vec<(&'a str, ReviewCredit)>,
+    login: &'a str,
+    blank: &ReviewCredit,
+) -> &'b mut ReviewCredit {
+    let i = match credits.iter().position(|(known, _)| *known == login) {
+        Some(i) => i,
+        None => {
+            credits.push((login, blank.clone()));
+            credits.len() - 1
+        }
+    };
+    &mut credits[i].1
+}
+
+// What a review counts for with --size-weighted-reviews: 1 on a pull
+// request of average size (`scale` lines), growing logarithmically with
+// the lines changed, and 0 on an empty one.
+fn review_size_factor(lines: u64, scale: f64) -> f64 {
+    if scale <= 0.0 {
+        return 1.0;
+    }
+    log_lines(lines, scale) / scale
+}
+
+// How an authored pull request's size turns into points.
+#[derive(Debug, Default, Clone, Copy, PartialEq, ValueEnum, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SizeScoring {
+    /// Every line counts the same
+    #[default]
+    Linear,
+    /// Each PR's lines count logarithmically, scaled so an average-sized PR scores as in linear
+    Log,
+    /// Each PR earns 1, 2 or 3 points as small, medium or large, times the average LOC per PR
+    Bucket,
+}
+
+// How the points of each kind of contribution combine into a score.
+#[derive(Debug, Default, Clone, Copy, PartialEq, ValueEnum, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScoreMode {
+    /// The points of every contribution added up
+    #[default]
+    Additive,
+    /// How far above the cohort a user is at reviewing, authoring and lines changed, favoring all three over one
+    Balanced,
+}
+
+// Standardized scores are capped so that one extreme outlier can't swamp the
+// other dimensions.
+const MAX_Z_SCORE: f64 = 3.0;
+
+// The mean and population standard deviation.
+fn mean_and_spread(values: &[f64]) -> (f64, f64) {
+    if values.is_empty() {
+        return (0.0, 0.0);
+    }
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let variance = values.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / n;
+    (mean, variance.sqrt())
+}
+
+// Each user's value on each dimension as standard deviations from the
+// cohort's mean. A dimension everyone scores the same on gives 0.
+fn z_scores(dimensions: &[[f64; 3]]) -> Vec<[f64; 3]> {
+    let mut z = vec![[0.0; 3]; dimensions.len()];
+    for d in 0..3 {
+        let values: Vec<f64> = dimensions.iter().map(|user| user[d]).collect();
+        let (mean, spread) = mean_and_spread(&values);
+        if spread == 0.0 {
+            continue;
+        }
+        for (z, value) in z.iter_mut().zip(values) {
+            z[d] = ((value - mean) / spread).clamp(-MAX_Z_SCORE, MAX_Z_SCORE);
+        }
+    }
+    z
+}
+
+// A pull request is small up to the first limits, medium up to the second,
+// and large past either of those.
+const SMALL_PR_LINES: u64 = 50;
+const SMALL_PR_FILES: u64 = 5;
+const MEDIUM_PR_LINES: u64 = 500;
+const MEDIUM_PR_FILES: u64 = 20;
+
+#[derive(Debug, Clone)]
+pub struct PullRequestSize {
+    pub additions: u64,
+    pub deletions: u64,
+    pub changed_files: u64,
+    pub date: DateTime<Utc>,
+}
+
+impl PullRequestSize {
+    fn bucket(&self) -> u64 {
+        let lines = self.additions + self.deletions;
+        if lines <= SMALL_PR_LINES && self.changed_files <= SMALL_PR_FILES {
+            1
+        } else if lines <= MEDIUM_PR_LINES && self.changed_files <= MEDIUM_PR_FILES {
+            2
+        } else {
+            3
+        }
+    }
+}
+
+// Lines compressed logarithmically. A PR of average size (`scale` lines)
+// keeps its line count; one ten times bigger counts far less than ten times.
+fn log_lines(lines: u64, scale: f64) -> f64 {
+    if lines == 0 {
+        return 0.0;
+    }
+    scale * (lines as f64).ln_1p() / scale.ln_1p()
+}
+
+impl ScoredUser {
+    // Rescales every score linearly so the top scorer is 100. When nobody
+    // scored anything, everyone normalizes to 0.
+    pub fn normalize(&mut self) {
+        let top = self.0.iter().map(|(_, stats)| stats.score).fold(0.0, f64::max);
+        for (_, stats) in self.0.iter_mut() {
+            stats.normalized_score = Some(if top == 0.0 {
+                0.0
+            } else {
+                stats.score * 100.0 / top
+            });
+        }
+    }
+
+    // Drops users who authored fewer than `min_prs` pull requests and did no
+    // reviewing or commenting, and returns their logins.
+    pub fn drop_occasional(&mut self, min_prs: u64) -> HashSet<String> {
+        let dropped: HashSet<String> = self
+            .0
+            .iter()
+            .filter(|(_, stats)| stats.pull_requests < min_prs && !stats.has_reviewed())
+            .map(|(user, _)| user.clone())
+            .collect();
+        self.drop_users(&dropped);
+        dropped
+    }
+
+    pub fn drop_users(&mut self, logins: &HashSet<String>) {
+        self.0.retain(|(user, _)| !logins.contains(user));
+    }
+
+    // Drops everyone but one login, matched regardless of case.
+    pub fn keep_user(&mut self, login: &str) {
+        self.0.retain(|(user, _)| user.eq_ignore_ascii_case(login));
+    }
+
+    pub fn anonymize(&mut self, pseudonyms: &mut Pseudonyms) -> Result<()> {
+        for (user, stats) in self.0.iter_mut() {
+            *user = pseudonyms.get(user)?;
+            stats.login = user.clone();
+        }
+        Ok(())
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum, Serialize)]
+#[value(rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum Field {
+    User,
+    Score,
+    NormalizedScore,
+    Approvals,
+    Comments,
+    ReviewComments,
+    RequestedChanges,
+    PrsReviewed,
+    #[value(name = "prs", alias = "pull_requests")]
+    #[serde(rename = "prs")]
+    PullRequests,
+    Additions,
+    Deletions,
+    ChangedFiles,
+    AvgChangedFilesPerPr,
+    ReviewRatio,
+    CommentWords,
+    ReviewsDismissed,
+    UnblockingApprovals,
+    SelfMerges,
+    Breakdown,
+}
+
+const DEFAULT_FIELDS: [Field; 18] = [
+    Field::User,
+    Field::Score,
+    Field::NormalizedScore,
+    Field::Approvals,
+    Field::Comments,
+    Field::ReviewComments,
+    Field::RequestedChanges,
+    Field::PrsReviewed,
+    Field::PullRequests,
+    Field::Additions,
+    Field::Deletions,
+    Field::ChangedFiles,
+    Field::AvgChangedFilesPerPr,
+    Field::ReviewRatio,
+    Field::CommentWords,
+    Field::ReviewsDismissed,
+    Field::UnblockingApprovals,
+    Field::SelfMerges,
+];
+
+const TABLE_FIELDS: [Field; 11] = [
+    Field::User,
+    Field::Score,
+    Field::Approvals,
+    Field::Comments,
+    Field::ReviewComments,
+    Field::RequestedChanges,
+    Field::PrsReviewed,
+    Field::PullRequests,
+    Field::Additions,
+    Field::Deletions,
+    Field::ChangedFiles,
+];
+
+impl Field {
+    // Column name in csv and table output.
+    fn header(&self) -> &'static str {
+        match self {
+            Field::User => "login",
+            _ => self.key(),
+        }
+    }
+
+    fn key(&self) -> &'static str {
+        match self {
+            Field::User => "user",
+            Field::Score => "score",
+            Field::NormalizedScore => "normalized_score",
+            Field::Approvals => "approvals",
+            Field::Comments => "comments",
+            Field::ReviewComments => "review_comments",
+            Field::RequestedChanges => "requested_changes",
+            Field::PrsReviewed => "prs_reviewed",
+            Field::PullRequests => "pull_requests",
+            Field::Additions => "additions",
+            Field::Deletions => "deletions",
+            Field::ChangedFiles => "changed_files",
+            Field::AvgChangedFilesPerPr => "avg_changed_files_per_pr",
+            Field::ReviewRatio => "review_ratio",
+            Field::CommentWords => "comment_words",
+            Field::ReviewsDismissed => "reviews_dismissed",
+            Field::UnblockingApprovals => "unblocking_approvals",
+            Field::SelfMerges => "self_merges",
+            Field::Breakdown => "breakdown",
+        }
+    }
+
+    // HELP text of the field's Prometheus metric family.
+    fn description(&self) -> &'static str {
+        match self {
+            Field::User => "GitHub login",
+            Field::Score => "Weighted contribution score",
+            Field::NormalizedScore => "Score rescaled to 0-100 relative to the top scorer",
+            Field::Approvals => "Approving reviews submitted",
+            Field::Comments => "Pull request comments and commented reviews",
+            Field::ReviewComments => "Inline comments attached to reviews",
+            Field::RequestedChanges => "Reviews requesting changes",
+            Field::PrsReviewed => "Distinct pull requests reviewed",
+            Field::PullRequests => "Pull requests authored",
+            Field::Additions => "Lines added in authored pull requests",
+            Field::Deletions => "Lines deleted in authored pull requests",
+            Field::ChangedFiles => "Files changed in authored pull requests",
+            Field::AvgChangedFilesPerPr => "Average files changed per authored pull request",
+            Field::ReviewRatio => "Approvals, comments and requested changes per authored pull request",
+            Field::CommentWords => "Words written in reviews and comments",
+            Field::ReviewsDismissed => "Reviews dismissed by later pushes",
+            Field::UnblockingApprovals => "Approvals that unblocked a merge",
+            Field::SelfMerges => "Authored pull requests merged by their author",
+            Field::Breakdown => "Points each kind of contribution adds to the score",
+        }
+    }
+
+    // None when the field has no value for this user, e.g. the normalized
+    // score when --normalize-score is off.
+    fn value(&self, user: &str, stats: &UserStats) -> Option<serde_json::Value> {
+        let value = match self {
+            Field::User => serde_json::json!(user),
+            Field::Score => serde_json::json!((stats.score * 100.0).round() / 100.0),
+            Field::NormalizedScore => serde_json::json!(stats.normalized_score?),
+            Field::Approvals => serde_json::json!(stats.approvals),
+            Field::Comments => serde_json::json!(stats.comments),
+            Field::ReviewComments => serde_json::json!(stats.review_comments),
+            Field::RequestedChanges => serde_json::json!(stats.requested_changes),
+            Field::PrsReviewed => serde_json::json!(stats.prs_reviewed),
+            Field::PullRequests => serde_json::json!(stats.pull_requests),
+            Field::Additions => serde_json::json!(stats.additions),
+            Field::Deletions => serde_json::json!(stats.deletions),
+            Field::ChangedFiles => serde_json::json!(stats.changed_files),
+            Field::AvgChangedFilesPerPr => serde_json::json!(stats.avg_changed_files_per_pr?),
+            Field::ReviewRatio => serde_json::json!(stats.review_ratio?),
+            Field::CommentWords => serde_json::json!(stats.comment_words),
+            Field::ReviewsDismissed => serde_json::json!(stats.reviews_dismissed),
+            Field::UnblockingApprovals => serde_json::json!(stats.unblocking_approvals),
+            Field::SelfMerges => serde_json::json!(stats.self_merges),
+            Field::Breakdown => serde_json::Value::Object(
+                stats
+                    .breakdown
+                    .parts()
+                    .iter()
+                    .map(|(part, points)| (part.to_string(), serde_json::json!((points * 100.0).round() / 100.0)))
+                    .collect(),
+            ),
+        };
+        Some(value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+    /// One JSON document holding the leaderboard and every enabled report
+    Json,
+    /// The leaderboard only, one row per user
+    Csv,
+    /// Aligned columns followed by the enabled reports as text
+    Table,
+    /// Prometheus text exposition format, one gauge per field
+    Prometheus,
+    /// The leaderboard only, one JSON object per user per line (NDJSON)
+    JsonLines,
+    /// The table, with a sparkline of each user's merged PRs per week
+    TerminalChart,
+}
+
+impl Format {
+    pub fn default_fields(&self, normalize_score: bool, explain: bool) -> Vec<Field> {
+        let mut fields = match self {
+            Format::Json | Format::JsonLines | Format::Prometheus => DEFAULT_FIELDS.to_vec(),
+            Format::Csv | Format::Table | Format::TerminalChart => {
+                let mut fields = TABLE_FIELDS.to_vec();
+                if normalize_score {
+                    fields.insert(2, Field::NormalizedScore);
+                }
+                fields
+            }
+        };
+        if explain {
+            fields.push(Field::Breakdown);
+        }
+        fields
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JsonShape {
+    /// {"users": [{"user": ..., ...}]} in rank order
+    Array,
+    /// {"users": {"<login>": {...}}} keyed by login, still in rank order
+    Object,
+}
+
+impl ScoredUser {
+    fn user_fields(
+        user: &str,
+        stats: &UserStats,
+        fields: &[Field],
+    ) -> serde_json::Map<String, serde_json::Value> {
+        let mut map = serde_json::Map::new();
+        for field in fields.iter().filter(|field| **field != Field::User) {
+            if let Some(value) = field.value(user, stats) {
+                map.insert(field.key().to_string(), value);
+            }
+        }
+        map
+    }
+
+    // One sample per user with a value for the field, e.g.
+    // git_stats_approvals{user="octocat",owner="acme"} 12.
+    fn write_samples(
+        &self,
+        out: &mut impl Write,
+        metric: &str,
+        field: Field,
+        labels: &[(&str, &str)],
+    ) -> std::fmt::Result {
+        for (user, stats) in self.0.iter() {
+            let Some(value) = field.value(user, stats) else {
+                continue;
+            };
+            let labels: Vec<String> = std::iter::once(("user", user.as_str()))
+                .chain(labels.iter().copied())
+                .map(|(name, value)| format!("{}=\"{}\"", name, prometheus_escape(value)))
+                .collect();
+            writeln!(out, "{}{{{}}} {}", metric, labels.join(","), value)?;
+        }
+        Ok(())
+    }
+
+    // Every entry carries its login, even when --fields omits it.
+    fn user_entry(user: &str, stats: &UserStats, fields: &[Field]) -> serde_json::Value {
+        let mut map = serde_json::Map::new();
+        map.insert("user".to_string(), serde_json::json!(user));
+        map.extend(ScoredUser::user_fields(user, stats, fields));
+        serde_json::Value::Object(map)
+    }
+
+    // The entries of the JSON array shape, one compact object per line in
+    // rank order.
+    // One line per user, flushed as it is written, so a pipeline reading
+    // stdout gets each user without waiting for the rest.
+    fn write_json_lines(&self, out: &mut impl io::Write, fields: &[Field]) -> Result<()> {
+        for (user, stats) in self.0.iter() {
+            let line = serde_json::to_string(&ScoredUser::user_entry(user, stats, fields))?;
+            out.write_all(format!("{}\n", line).as_bytes())?;
+            out.flush()?;
+        }
+        Ok(())
+    }
+
+    fn to_json(&self, fields: &[Field], shape: JsonShape) -> serde_json::Value {
+        match shape {
+            JsonShape::Array => serde_json::Value::Array(
+                self.0
+                    .iter()
+                    .map(|(user, stats)| ScoredUser::user_entry(user, stats, fields))
+                    .collect(),
+            ),
+            JsonShape::Object => serde_json::Value::Object(
+                self.0
+                    .iter()
+                    .map(|(user, stats)| {
+                        (
+                            user.clone(),
+                            serde_json::Value::Object(ScoredUser::user_fields(user, stats, fields)),
+                        )
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    // The login always comes first, whether or not --fields lists it.
+    fn rows(&self, fields: &[Field], format: Format, group_digits: bool) -> Vec<Vec<String>> {
+        let columns: Vec<Field> = std::iter::once(Field::User)
+            .chain(fields.iter().copied().filter(|field| *field != Field::User))
+            .collect();
+        let mut rows = vec![columns
+            .iter()
+            .flat_map(|field| match field {
+                // The breakdown takes a column per part, e.g. approvals_points.
+                Field::Breakdown => ScoreBreakdown::default()
+                    .parts()
+                    .iter()
+                    .map(|(part, _)| format!("{}_points", part))
+                    .collect(),
+                field => vec![field.header().to_string()],
+            })
+            .collect()];
+        for (user, stats) in self.0.iter() {
+            rows.push(
+                columns
+                    .iter()
+                    .flat_map(|field| match field.value(user, stats) {
+                        Some(serde_json::Value::Object(parts)) => {
+                            parts.into_iter().map(|(_, points)| cell(Some(points), format, group_digits)).collect()
+                        }
+                        // Reviewed without authoring: the ratio is unbounded.
+                        None if *field == Field::ReviewRatio && stats.reviews() > 0 => vec!["inf".to_string()],
+                        value => vec![cell(value, format, group_digits)],
+                    })
+                    .collect(),
+            );
+        }
+        rows
+    }
+
+    fn write_csv(&self, out: &mut impl Write, fields: &[Field]) -> std::fmt::Result {
+        for row in self.rows(fields, Format::Csv, false) {
+            let row: Vec<String> = row.iter().map(|value| csv_escape(value)).collect();
+            writeln!(out, "{}", row.join(","))?;
+        }
+        Ok(())
+    }
+
+    // With activity, a last column holds each user's weekly sparkline.
+    fn write_table(
+        &self,
+        out: &mut impl Write,
+        fields: &[Field],
+        group_digits: bool,
+        activity: Option<(&WeeklyActivity, &DateBounds)>,
+    ) -> std::fmt::Result {
+        let mut rows = self.rows(fields, Format::Table, group_digits);
+        if let Some((activity, span)) = activity {
+            rows[0].push("activity".to_string());
+            for (row, (user, _)) in rows.iter_mut().skip(1).zip(self.0.iter()) {
+                row.push(activity.sparkline(user, span));
+            }
+        }
+        write_aligned(out, &rows)
+    }
+}
+
+// Logins are left-aligned, numbers right-aligned.
+fn write_aligned(out: &mut impl Write, rows: &[Vec<String>]) -> std::fmt::Result {
+    let mut widths = vec![0; rows[0].len()];
+    for row in rows.iter() {
+        for (width, value) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(value.chars().count());
+        }
+    }
+    for row in rows.iter() {
+        let mut line = format!("{:<width$}", row[0], width = widths[0]);
+        for (value, width) in row.iter().zip(widths.iter()).skip(1) {
+            line.push_str(&format!("  {:>width$}", value, width = width));
+        }
+        writeln!(out, "{}", line.trim_end())?;
+    }
+    Ok(())
+}
+
+// Empty when the field has no value. Floats keep full precision in csv and
+// are rounded to two places in the table, where numbers can also have their
+// digits grouped.
+fn cell(value: Option<serde_json::Value>, format: Format, group: bool) -> String {
+    match value {
+        None => String::new(),
+        Some(serde_json::Value::String(s)) => s,
+        Some(serde_json::Value::Number(n)) if format == Format::Table => {
+            let n = if n.is_f64() {
+                format!("{:.2}", n.as_f64().unwrap_or_default())
+            } else {
+                n.to_string()
+            };
+            if group {
+                group_digits(&n)
+            } else {
+                n
+            }
+        }
+        Some(value) => value.to_string(),
+    }
+}
+
+// Thousands separators in a formatted number: 1234567.5 becomes
+// 1,234,567.5. Only the digits before the decimal point are grouped.
+fn group_digits(number: &str) -> String {
+    let (sign, rest) = match number.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", number),
+    };
+    let (whole, fraction) = match rest.find('.') {
+        Some(i) => rest.split_at(i),
+        None => (rest, ""),
+    };
+    let mut grouped = String::new();
+    for (i, digit) in whole.chars().enumerate() {
+        if i > 0 && (whole.len() - i) % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    format!("{}{}{}", sign, grouped, fraction)
+}
+
+// Label values escape backslashes, double quotes and newlines.
+fn prometheus_escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn csv_escape(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// The results of a run: the leaderboard plus every enabled report.
+pub struct Report {
+    // The --owner or --enterprise the run covered.
+    pub owner: String,
+    pub leaderboard: Leaderboard,
+    pub by_repo: Option<BTreeMap<String, Leaderboard>>,
+    pub by_language: Option<BTreeMap<String, Leaderboard>>,
+    pub summary: Summary,
+    pub data_span: DateBounds,
+    pub approval_latency: Option<ApprovalLatencyReport>,
+    pub cycle_time: Option<CycleTimeReport>,
+    pub merge_methods: Option<MergeMethods>,
+    pub reciprocity: Option<Reciprocity>,
+    pub most_discussed: Option<DiscussedPrs>,
+    // Merge dates per author, for --format terminal-chart.
+    pub activity: Option<WeeklyActivity>,
+    pub warnings: Warnings,
+}
+
+pub struct Leaderboard {
+    pub users: ScoredUser,
+    pub unattributable: Option<Unattributable>,
+}
+
+impl Leaderboard {
+    fn to_json(&self, fields: &[Field], shape: JsonShape) -> serde_json::Map<String, serde_json::Value> {
+        let mut map = serde_json::Map::new();
+        map.insert("users".to_string(), self.users.to_json(fields, shape));
+        if let Some(unattributable) = &self.unattributable {
+            map.insert("unattributable".to_string(), serde_json::json!(unattributable));
+        }
+        map
+    }
+
+    fn write_table(
+        &self,
+        out: &mut impl Write,
+        fields: &[Field],
+        group_digits: bool,
+        activity: Option<(&WeeklyActivity, &DateBounds)>,
+    ) -> std::fmt::Result {
+        self.users.write_table(out, fields, group_digits, activity)?;
+        if let Some(unattributable) = &self.unattributable {
+            if group_digits {
+                write!(out, "{:#}", unattributable)?;
+            } else {
+                write!(out, "{}", unattributable)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Bumped whenever the JSON document changes in a way that could break a
+// consumer: a key renamed, removed or given a different type.
+pub const JSON_SCHEMA_VERSION: u32 = 1;
+
+// The --format json document as described by --print-schema: the version
+// marker, the leaderboard in its default array shape, and the summary. The
+// optional reports are present when requested but not described.
+#[derive(JsonSchema)]
+pub struct JsonReport {
+    pub schema_version: u32,
+    pub users: Vec<JsonUser>,
+    pub unattributable: Option<Unattributable>,
+    pub summary: Summary,
+    pub data_span: DateBounds,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct JsonUser {
+    pub user: String,
+    #[serde(flatten)]
+    pub stats: UserStats,
+}
+
+pub fn json_schema() -> serde_json::Value {
+    serde_json::json!(schemars::schema_for!(JsonReport))
+}
+
+// A previous --format json report, read back by --merge-from. Only the
+// leaderboard, the unattributable line, the summary and the data span are
+// used; scores are ignored and computed again.
+#[derive(Deserialize)]
+pub struct PriorReport {
+    pub schema_version: Option<u32>,
+    pub users: PriorUsers,
+    pub unattributable: Option<Unattributable>,
+    pub summary: Summary,
+    pub data_span: Option<DateBounds>,
+}
+
+// Either --json-shape.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum PriorUsers {
+    Array(Vec<JsonUser>),
+    Object(BTreeMap<String, UserStats>),
+}
+
+impl PriorReport {
+    pub fn read(path: &Path) -> Result<PriorReport> {
+        let text = fs::read_to_string(path).map_err(|e| anyhow::anyhow!("can't read {}: {}", path.display(), e))?;
+        let prior: PriorReport = serde_json::from_str(&text)
+            .map_err(|e| anyhow::anyhow!("{} is not a git-stats JSON report: {}", path.display(), e))?;
+        match prior.schema_version {
+            Some(JSON_SCHEMA_VERSION) => Ok(prior),
+            Some(version) => Err(anyhow::anyhow!(
+                "{} has schema version {}, this version of git-stats reads {}",
+                path.display(),
+                version,
+                JSON_SCHEMA_VERSION
+            )),
+            None => Err(anyhow::anyhow!("{} has no schema_version", path.display())),
+        }
+    }
+
+    pub fn users(&self) -> Vec<(&str, &UserStats)> {
+        match &self.users {
+            PriorUsers::Array(users) => users.iter().map(|user| (user.user.as_str(), &user.stats)).collect(),
+            PriorUsers::Object(users) => users.iter().map(|(login, stats)| (login.as_str(), stats)).collect(),
+        }
+    }
+}
+
+impl Report {
+    // A single JSON document so stdout can be piped straight into other
+    // tools. Optional reports are left out unless they were requested.
+    fn to_json(&self, fields: &[Field], shape: JsonShape) -> serde_json::Value {
+        let mut report = serde_json::Map::new();
+        report.insert("schema_version".to_string(), serde_json::json!(JSON_SCHEMA_VERSION));
+        report.extend(self.leaderboard.to_json(fields, shape));
+        report.insert("summary".to_string(), serde_json::json!(self.summary));
+        for (key, groups) in [("by_repo", &self.by_repo), ("by_language", &self.by_language)] {
+            if let Some(groups) = groups {
+                let groups = groups
+                    .iter()
+                    .map(|(name, leaderboard)| {
+                        (name.clone(), serde_json::Value::Object(leaderboard.to_json(fields, shape)))
+                    })
+                    .collect();
+                report.insert(key.to_string(), serde_json::Value::Object(groups));
+            }
+        }
+        report.insert("data_span".to_string(), serde_json::json!(self.data_span));
+        let sections = [
+            ("approval_latency", self.approval_latency.as_ref().map(|s| serde_json::json!(s))),
+            ("cycle_time", self.cycle_time.as_ref().map(|s| serde_json::json!(s))),
+            ("merge_methods", self.merge_methods.as_ref().map(|s| serde_json::json!(s))),
+            ("reciprocity", self.reciprocity.as_ref().map(|s| serde_json::json!(s))),
+            ("most_discussed", self.most_discussed.as_ref().map(|s| serde_json::json!(s))),
+        ];
+        for (key, section) in sections {
+            if let Some(section) = section {
+                report.insert(key.to_string(), section);
+            }
+        }
+        report.insert("warnings".to_string(), serde_json::json!(self.warnings));
+        serde_json::Value::Object(report)
+    }
+
+    // Every field is a gauge family, labelled by user and owner. The
+    // --per-repo and --by-language groups repeat the samples with a repo or
+    // language label added.
+    fn write_prometheus(&self, out: &mut impl Write, fields: &[Field]) -> std::fmt::Result {
+        let owner = ("owner", self.owner.as_str());
+        for field in fields.iter().copied().filter(|field| *field != Field::User) {
+            let metric = format!("git_stats_{}", field.key());
+            writeln!(out, "# HELP {} {}", metric, field.description())?;
+            writeln!(out, "# TYPE {} gauge", metric)?;
+            self.leaderboard.users.write_samples(out, &metric, field, &[owner])?;
+            for (label, groups) in [("repo", &self.by_repo), ("language", &self.by_language)] {
+                for (group, leaderboard) in groups.iter().flatten() {
+                    leaderboard
+                        .users
+                        .write_samples(out, &metric, field, &[owner, (label, group.as_str())])?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Replaces every login in the report with its pseudonym. The
+    // unattributable label is left alone, as it names nobody.
+    pub fn anonymize(&mut self, pseudonyms: &mut Pseudonyms) -> Result<()> {
+        self.leaderboard.users.anonymize(pseudonyms)?;
+        for groups in [&mut self.by_repo, &mut self.by_language].into_iter().flatten() {
+            for leaderboard in groups.values_mut() {
+                leaderboard.users.anonymize(pseudonyms)?;
+            }
+        }
+        if let Some(latency) = &mut self.approval_latency {
+            latency.by_author = pseudonyms.keys(std::mem::take(&mut latency.by_author))?;
+            latency.by_reviewer = pseudonyms.keys(std::mem::take(&mut latency.by_reviewer))?;
+        }
+        if let Some(cycle_time) = &mut self.cycle_time {
+            cycle_time.by_author = pseudonyms.keys(std::mem::take(&mut cycle_time.by_author))?;
+        }
+        for pair in self.reciprocity.iter_mut().flat_map(|reciprocity| reciprocity.0.iter_mut()) {
+            pair.reviewer = pseudonyms.get(&pair.reviewer)?;
+            pair.author = pseudonyms.get(&pair.author)?;
+        }
+        // Only logins replaced elsewhere in the report are kept, so no new
+        // spellings end up in the map.
+        if let Some(activity) = &mut self.activity {
+            let mut merged = HashMap::new();
+            for (login, pseudonym) in pseudonyms.seen.iter() {
+                if let Some(dates) = activity.merged.remove(&login.to_lowercase()) {
+                    merged.insert(pseudonym.to_lowercase(), dates);
+                }
+            }
+            activity.merged = merged;
+        }
+        Ok(())
+    }
+
+    // With chart, the leaderboard gets a sparkline column; the groups don't,
+    // since the weekly counts are for the whole run.
+    fn write_table(&self, out: &mut impl Write, fields: &[Field], group_digits: bool, chart: bool) -> std::fmt::Result {
+        let activity = self.activity.as_ref().filter(|_| chart).map(|activity| (activity, &self.data_span));
+        self.leaderboard.write_table(out, fields, group_digits, activity)?;
+        if group_digits {
+            write!(out, "{:#}", self.summary)?;
+        } else {
+            write!(out, "{}", self.summary)?;
+        }
+        write!(out, "{}", self.data_span)?;
+        if let Some(repos) = &self.by_repo {
+            for (repo, leaderboard) in repos.iter() {
+                writeln!(out, "\nRepository {}:", repo)?;
+                leaderboard.write_table(out, fields, group_digits, None)?;
+            }
+        }
+        if let Some(languages) = &self.by_language {
+            for (language, leaderboard) in languages.iter() {
+                writeln!(out, "\n{}:", language)?;
+                leaderboard.write_table(out, fields, group_digits, None)?;
+            }
+        }
+        write!(out, "{}", self.warnings)?;
+        if let Some(latency) = &self.approval_latency {
+            write!(out, "\n{}", latency)?;
+        }
+        if let Some(cycle_time) = &self.cycle_time {
+            write!(out, "\n{}", cycle_time)?;
+        }
+        if let Some(merge_methods) = &self.merge_methods {
+            write!(out, "\n{}", merge_methods)?;
+        }
+        if let Some(reciprocity) = &self.reciprocity {
+            write!(out, "\n{}", reciprocity)?;
+        }
+        if let Some(discussed) = &self.most_discussed {
+            write!(out, "\n{}", discussed)?;
+        }
+        Ok(())
+    }
+}
+
+// Stable stand-ins for logins, for sharing results without naming anyone:
+// user-<the first 16 hex digits of SHA-256 over the key and the lowercased
+// login>. A login gets the same pseudonym in every run with the same key.
+pub struct Pseudonyms {
+    key: String,
+    // Every login replaced so far, with its pseudonym.
+    pub seen: BTreeMap<String, String>,
+    // The lowercased login behind each pseudonym handed out, to catch two
+    // logins hashing to the same one.
+    logins: HashMap<String, String>,
+}
+
+impl Pseudonyms {
+    pub fn new(key: &str) -> Pseudonyms {
+        Pseudonyms {
+            key: key.to_string(),
+            seen: BTreeMap::new(),
+            logins: HashMap::new(),
+        }
+    }
+
+    // The deleted-account placeholder names nobody, so it is kept.
+    pub fn get(&mut self, login: &str) -> Result<String> {
+        if login == DELETED_LOGIN {
+            return Ok(login.to_string());
+        }
+        let lowercase = login.to_lowercase();
+        let mut hasher = Sha256::new();
+        hasher.update(self.key.as_bytes());
+        hasher.update([0]);
+        hasher.update(lowercase.as_bytes());
+        let pseudonym = format!("user-{}", &format!("{:x}", hasher.finalize())[..16]);
+        match self.logins.get(&pseudonym) {
+            Some(other) if *other != lowercase => {
+                return Err(anyhow::anyhow!(
+                    "{} and {} have the same pseudonym {}; try another --anonymize-key",
+                    other,
+                    lowercase,
+                    pseudonym
+                ));
+            }
+            Some(_) => {}
+            None => {
+                self.logins.insert(pseudonym.clone(), lowercase);
+            }
+        }
+        self.seen.insert(login.to_string(), pseudonym.clone());
+        Ok(pseudonym)
+    }
+
+    // A login,pseudonym CSV of every login replaced, sorted by login.
+    pub fn write_map(&self, path: &Path) -> Result<()> {
+        let mut csv = String::from("login,pseudonym\n");
+        for (login, pseudonym) in self.seen.iter() {
+            csv.push_str(&format!("{},{}\n", csv_escape(login), pseudonym));
+        }
+        write_atomic(path, csv.as_bytes())
+    }
+
+    fn keys<T>(&mut self, map: BTreeMap<String, T>) -> Result<BTreeMap<String, T>> {
+        map.into_iter().map(|(login, value)| Ok((self.get(&login)?, value))).collect()
+    }
+}
+
+pub fn render(
+    report: &Report,
+    format: Format,
+    fields: &[Field],
+    shape: JsonShape,
+    group_digits: bool,
+) -> Result<String> {
+    let mut out = String::new();
+    match format {
+        Format::Json => {
+            out = serde_json::to_string_pretty(&report.to_json(fields, shape))?;
+            out.push('\n');
+        }
+        Format::Csv => report.leaderboard.users.write_csv(&mut out, fields)?,
+        Format::Table => report.write_table(&mut out, fields, group_digits, false)?,
+        Format::TerminalChart => report.write_table(&mut out, fields, group_digits, true)?,
+        Format::Prometheus => report.write_prometheus(&mut out, fields)?,
+        Format::JsonLines => {
+            let mut lines = Vec::new();
+            report.leaderboard.users.write_json_lines(&mut lines, fields)?;
+            out = String::from_utf8(lines)?;
+        }
+    }
+    Ok(out)
+}
+
+// --format json-lines straight to a writer such as stdout, a line at a time.
+pub fn write_json_lines(report: &Report, fields: &[Field], out: &mut impl io::Write) -> Result<()> {
+    report.leaderboard.users.write_json_lines(out, fields)
+}
+
+// With --output-dir, the overall leaderboard as _combined.csv and each
+// repository's as <repo>.csv, for loading into separate dashboards.
+pub fn write_csv_dir(report: &Report, fields: &[Field], dir: &Path) -> Result<()> {
+    let mut files = BTreeMap::new();
+    files.insert(COMBINED_CSV.to_string(), &report.leaderboard);
+    for (repo, leaderboard) in report.by_repo.iter().flatten() {
+        let name = format!("{}.csv", csv_file_stem(repo));
+        if files.insert(name.clone(), leaderboard).is_some() {
+            return Err(anyhow::anyhow!("{} would be written twice, once for repository {}", name, repo));
+        }
+    }
+    fs::create_dir_all(dir)?;
+    for (name, leaderboard) in files {
+        let mut csv = String::new();
+        leaderboard.users.write_csv(&mut csv, fields)?;
+        write_atomic(&dir.join(name), csv.as_bytes())?;
+    }
+    Ok(())
+}
+
+const COMBINED_CSV: &str = "_combined.csv";
+
+// A repository name, or org/repo across an enterprise, made safe as a file
+// name: anything but letters, digits, '-', '_' and '.' becomes '_', and so
+// does a leading '.', so nothing is hidden or climbs out of the directory.
+fn csv_file_stem(repo: &str) -> String {
+    repo.chars()
+        .enumerate()
+        .map(|(i, c)| match c {
+            '.' if i == 0 => '_',
+            c if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct Unattributable {
+    pub label: String,
+    pub pull_requests: u64,
+    pub approvals: u64,
+    pub comments: u64,
+    pub review_comments: u64,
+    pub requested_changes: u64,
+    pub additions: u64,
+    pub deletions: u64,
+}
+
+impl Unattributable {
+    pub fn new(label: &str, stats: &UserStats) -> Unattributable {
+        Unattributable {
+            label: label.to_string(),
+            pull_requests: stats.pull_requests,
+            approvals: stats.approvals,
+            comments: stats.comments,
+            review_comments: stats.review_comments,
+            requested_changes: stats.requested_changes,
+            additions: stats.additions,
+            deletions: stats.deletions,
+        }
+    }
+}
+
+// {:#} groups the digits of the counts.
+impl Display for Unattributable {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let count = |n: u64| if f.alternate() { group_digits(&n.to_string()) } else { n.to_string() };
+        writeln!(
+            f,
+            "Unattributable contributions ({}): {} pull requests, {} approvals, {} comments, {} review comments, {} requested changes, {} additions, {} deletions",
+            self.label,
+            count(self.pull_requests),
+            count(self.approvals),
+            count(self.comments),
+            count(self.review_comments),
+            count(self.requested_changes),
+            count(self.additions),
+            count(self.deletions)
+        )
+    }
+}
+
+// Org-wide totals over every merged pull request counted, for context on
+// the individual numbers. avg_loc_per_pr is the scale scores were built on.
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct Summary {
+    pub pull_requests: u64,
+    pub additions: u64,
+    pub deletions: u64,
+    pub reviewers: u64,
+    pub avg_loc_per_pr: f64,
+}
+
+// {:#} groups the digits of the numbers.
+impl Display for Summary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let number = |n: String| if f.alternate() { group_digits(&n) } else { n };
+        writeln!(
+            f,
+            "Totals: {} pull requests, {} additions, {} deletions, {} reviewers, {} average LOC per PR",
+            number(self.pull_requests.to_string()),
+            number(self.additions.to_string()),
+            number(self.deletions.to_string()),
+            number(self.reviewers.to_string()),
+            number(format!("{:.2}", self.avg_loc_per_pr))
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct DateBounds {
+    pub requested_since: Option<NaiveDate>,
+    pub requested_until: Option<NaiveDate>,
+    #[serde(rename = "earliest_merged_at")]
+    pub earliest: Option<DateTime<Utc>>,
+    #[serde(rename = "latest_merged_at")]
+    pub latest: Option<DateTime<Utc>>,
+}
+
+impl DateBounds {
+    pub fn new(window: DateWindow) -> DateBounds {
+        DateBounds {
+            requested_since: window.since,
+            requested_until: window.until,
+            earliest: None,
+            latest: None,
+        }
+    }
+
+    pub fn add(&mut self, merged_at: DateTime<Utc>) {
+        self.earliest = Some(self.earliest.map_or(merged_at, |earliest| earliest.min(merged_at)));
+        self.latest = Some(self.latest.map_or(merged_at, |latest| latest.max(merged_at)));
+    }
+
+    // Widens the merge dates found to cover another run's.
+    pub fn extend(&mut self, other: &DateBounds) {
+        for merged_at in [other.earliest, other.latest].into_iter().flatten() {
+            self.add(merged_at);
+        }
+    }
+}
+
+impl Display for DateBounds {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match (self.requested_since, self.requested_until) {
+            (Some(since), Some(until)) => write!(f, "Requested {} to {}", since, until)?,
+            (Some(since), None) => write!(f, "Requested since {}", since)?,
+            (None, Some(until)) => write!(f, "Requested until {}", until)?,
+            (None, None) => write!(f, "Requested all history")?,
+        }
+        match (self.earliest, self.latest) {
+            (Some(earliest), Some(latest)) => writeln!(
+                f,
+                ", actual data spans {} to {}",
+                earliest.date_naive(),
+                latest.date_naive()
+            ),
+            _ => writeln!(f, ", no merged pull requests were found"),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct DiscussedPrs(pub Vec<DiscussedPr>);
+
+#[derive(Serialize)]
+pub struct DiscussedPr {
+    pub repo: String,
+    pub number: u64,
+    pub title: String,
+    pub url: String,
+    pub comments: u64,
+}
+
+impl DiscussedPrs {
+    pub fn top(mut self, n: usize) -> DiscussedPrs {
+        self.0.sort_by_key(|pr| std::cmp::Reverse(pr.comments));
+        self.0.truncate(n);
+        self
+    }
+}
+
+impl Display for DiscussedPrs {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Most discussed pull requests:")?;
+        for pr in self.0.iter() {
+            writeln!(
+                f,
+                "  {}#{} ({} comments) {} {}",
+                pr.repo, pr.number, pr.comments, pr.title, pr.url
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct ApprovalLatency {
+    pub by_author: HashMap<String, Vec<f64>>,
+    pub by_reviewer: HashMap<String, Vec<f64>>,
+}
+
+impl ApprovalLatency {
+    pub fn new() -> ApprovalLatency {
+        ApprovalLatency {
+            by_author: HashMap::new(),
+            by_reviewer: HashMap::new(),
+        }
+    }
+
+    pub fn add_pull_request(&mut self, pr: &PullRequest) {
+        // Deleted accounts are reported as unattributable, not per user.
+        let latencies = pr.approval_latencies();
+        if let Some((_, first)) = latencies.first() {
+            if pr.author.login != DELETED_LOGIN {
+                self.by_author
+                    .entry(pr.author.login.clone())
+                    .or_default()
+                    .push(hours(first));
+            }
+        }
+        for (reviewer, latency) in latencies {
+            if reviewer == DELETED_LOGIN {
+                continue;
+            }
+            self.by_reviewer
+                .entry(reviewer)
+                .or_default()
+                .push(hours(&latency));
+        }
+    }
+
+    pub fn report(&self) -> ApprovalLatencyReport {
+        ApprovalLatencyReport {
+            by_author: ApprovalLatency::summarize(&self.by_author),
+            by_reviewer: ApprovalLatency::summarize(&self.by_reviewer),
+        }
+    }
+
+    fn summarize(latencies: &HashMap<String, Vec<f64>>) -> BTreeMap<String, LatencySummary> {
+        latencies
+            .iter()
+            .map(|(user, hours)| {
+                let mut hours = hours.clone();
+                hours.sort_by(|a, b| a.total_cmp(b));
+                let summary = LatencySummary {
+                    avg_hours: hours.iter().sum::<f64>() / hours.len() as f64,
+                    p50_hours: percentile(&hours, 50.0),
+                    p90_hours: percentile(&hours, 90.0),
+                    pull_requests: hours.len(),
+                };
+                (user.clone(), summary)
+            })
+            .collect()
+    }
+}
+
+#[derive(Serialize)]
+pub struct ApprovalLatencyReport {
+    pub by_author: BTreeMap<String, LatencySummary>,
+    pub by_reviewer: BTreeMap<String, LatencySummary>,
+}
+
+#[derive(Serialize)]
+pub struct LatencySummary {
+    pub avg_hours: f64,
+    p50_hours: f64,
+    p90_hours: f64,
+    pub pull_requests: usize,
+}
+
+impl ApprovalLatencyReport {
+    fn write_section(
+        f: &mut Formatter<'_>,
+        heading: &str,
+        latencies: &BTreeMap<String, LatencySummary>,
+    ) -> std::fmt::Result {
+        writeln!(f, "  {}:", heading)?;
+        for (user, summary) in latencies.iter() {
+            writeln!(
+                f,
+                "    {}: avg {:.1}h, p50 {:.1}h, p90 {:.1}h ({} PRs)",
+                user, summary.avg_hours, summary.p50_hours, summary.p90_hours, summary.pull_requests
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for ApprovalLatencyReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Time to first approval:")?;
+        ApprovalLatencyReport::write_section(f, "By author", &self.by_author)?;
+        ApprovalLatencyReport::write_section(f, "By reviewer", &self.by_reviewer)?;
+        Ok(())
+    }
+}
+
+// Time from opening a pull request to its first review and to its merge,
+// per author and per repository. Pull requests nobody reviewed are left out
+// of the first-review average rather than counted as zero.
+#[derive(Default)]
+pub struct CycleTime {
+    pub by_author: HashMap<String, CycleTimes>,
+    pub by_repo: HashMap<String, CycleTimes>,
+}
+
+#[derive(Default)]
+pub struct CycleTimes {
+    pub pull_requests: usize,
+    pub first_review_hours: Vec<f64>,
+    pub merge_hours: Vec<f64>,
+}
+
+impl CycleTimes {
+    fn add(&mut self, first_review: Option<f64>, merge: Option<f64>) {
+        self.pull_requests += 1;
+        self.first_review_hours.extend(first_review);
+        self.merge_hours.extend(merge);
+    }
+
+    fn summary(&self) -> CycleTimeSummary {
+        let average = |hours: &[f64]| {
+            (!hours.is_empty()).then(|| hours.iter().sum::<f64>() / hours.len() as f64)
+        };
+        CycleTimeSummary {
+            pull_requests: self.pull_requests,
+            reviewed_pull_requests: self.first_review_hours.len(),
+            avg_hours_to_first_review: average(&self.first_review_hours),
+            merged_pull_requests: self.merge_hours.len(),
+            avg_hours_to_merge: average(&self.merge_hours),
+        }
+    }
+}
+
+impl CycleTime {
+    pub fn new() -> CycleTime {
+        CycleTime {
+            by_author: HashMap::new(),
+            by_repo: HashMap::new(),
+        }
+    }
+
+    pub fn add_pull_request(&mut self, repo: &str, pr: &PullRequest) {
+        let first_review = pr.first_review_latency().map(|latency| hours(&latency));
+        let merge = pr.merged_at.map(|merged_at| hours(&(merged_at - pr.created_at)));
+        // Deleted accounts are reported as unattributable, not per user.
+        if pr.author.login != DELETED_LOGIN {
+            self.by_author
+                .entry(pr.author.login.clone())
+                .or_default()
+                .add(first_review, merge);
+        }
+        self.by_repo
+            .entry(repo.to_string())
+            .or_default()
+            .add(first_review, merge);
+    }
+
+    pub fn report(&self) -> CycleTimeReport {
+        let summarize = |times: &HashMap<String, CycleTimes>| {
+            times
+                .iter()
+                .map(|(name, times)| (name.clone(), times.summary()))
+                .collect()
+        };
+        CycleTimeReport {
+            by_author: summarize(&self.by_author),
+            by_repo: summarize(&self.by_repo),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct CycleTimeReport {
+    pub by_author: BTreeMap<String, CycleTimeSummary>,
+    pub by_repo: BTreeMap<String, CycleTimeSummary>,
+}
+
+#[derive(Serialize)]
+pub struct CycleTimeSummary {
+    pub pull_requests: usize,
+    pub reviewed_pull_requests: usize,
+    pub avg_hours_to_first_review: Option<f64>,
+    pub merged_pull_requests: usize,
+    pub avg_hours_to_merge: Option<f64>,
+}
+
+impl CycleTimeReport {
+    fn write_section(
+        f: &mut Formatter<'_>,
+        heading: &str,
+        summaries: &BTreeMap<String, CycleTimeSummary>,
+    ) -> std::fmt::Result {
+        writeln!(f, "  {}:", heading)?;
+        for (name, summary) in summaries.iter() {
+            let average = |hours: Option<f64>| match hours {
+                Some(hours) => format!("{:.1}h", hours),
+                None => "n/a".to_string(),
+            };
+            writeln!(
+                f,
+                "    {}: first review avg {} ({} of {} PRs reviewed), merge avg {} ({} merged)",
+                name,
+                average(summary.avg_hours_to_first_review),
+                summary.reviewed_pull_requests,
+                summary.pull_requests,
+                average(summary.avg_hours_to_merge),
+                summary.merged_pull_requests
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for CycleTimeReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Time from opening to first review and to merge:")?;
+        CycleTimeReport::write_section(f, "By author", &self.by_author)?;
+        CycleTimeReport::write_section(f, "By repository", &self.by_repo)?;
+        Ok(())
+    }
+}
+
+fn hours(duration: &chrono::Duration) -> f64 {
+    duration.num_seconds() as f64 / 3600.0
+}
+
+// Nearest-rank percentile of an already sorted, non-empty slice.
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+// How many PRs each reviewer reviewed for each author, keyed by
+// (reviewer, author). Self-reviews and deleted accounts are skipped.
+pub struct ReviewPairs(pub HashMap<(String, String), u64>);
+
+impl ReviewPairs {
+    pub fn add_pull_request(&mut self, pr: &PullRequest) {
+        let author = &pr.author.login;
+        if author == DELETED_LOGIN {
+            return;
+        }
+        let mut reviewers: Vec<&String> = pr
+            .reviews
+            .nodes
+            .iter()
+            .map(|review| &review.author.login)
+            .filter(|reviewer| *reviewer != author && *reviewer != DELETED_LOGIN)
+            .collect();
+        reviewers.sort();
+        reviewers.dedup();
+        for reviewer in reviewers {
+            *self.0.entry((reviewer.clone(), author.clone())).or_insert(0) += 1;
+        }
+    }
+
+    // Pairs ordered from most to least one-sided. Each pair is listed once,
+    // with the heavier direction first.
+    pub fn most_imbalanced(&self, n: usize) -> Reciprocity {
+        let mut pairs: Vec<PairBalance> = Vec::new();
+        for ((reviewer, author), given) in self.0.iter() {
+            let returned = self
+                .0
+                .get(&(author.clone(), reviewer.clone()))
+                .copied()
+                .unwrap_or(0);
+            if *given > returned || (*given == returned && reviewer < author) {
+                pairs.push(PairBalance {
+                    reviewer: reviewer.clone(),
+                    author: author.clone(),
+                    given: *given,
+                    returned,
+                    // 1.0 when both review each other equally, 0.0 when it only goes one way.
+                    reciprocity: returned.min(*given) as f64 / (*given).max(returned) as f64,
+                });
+            }
+        }
+        pairs.sort_by(|a, b| {
+            b.imbalance()
+                .cmp(&a.imbalance())
+                .then(a.reciprocity.total_cmp(&b.reciprocity))
+                .then(a.reviewer.cmp(&b.reviewer))
+                .then(a.author.cmp(&b.author))
+        });
+        pairs.truncate(n);
+        Reciprocity(pairs)
+    }
+}
+
+// given is how many of author's PRs reviewer reviewed, returned is the
+// reverse.
+#[derive(Serialize)]
+pub struct PairBalance {
+    pub reviewer: String,
+    pub author: String,
+    pub given: u64,
+    pub returned: u64,
+    pub reciprocity: f64,
+}
+
+impl PairBalance {
+    fn imbalance(&self) -> u64 {
+        self.given.abs_diff(self.returned)
+    }
+}
+
+#[derive(Serialize)]
+pub struct Reciprocity(pub Vec<PairBalance>);
+
+impl Display for Reciprocity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Least reciprocal review pairs:")?;
+        for pair in self.0.iter() {
+            writeln!(
+                f,
+                "  {} reviewed {} on {} PRs, {} reviewed {} on {} (reciprocity {:.2})",
+                pair.reviewer,
+                pair.author,
+                pair.given,
+                pair.author,
+                pair.reviewer,
+                pair.returned,
+                pair.reciprocity
+            )?;
+        }
+        Ok(())
+    }
+}
+
+pub enum MergeMethod {
+    Merge,
+    Squash,
+    Rebase,
+    Unknown,
+}
+
+#[derive(Default, Serialize)]
+pub struct MergeMethods {
+    #[serde(rename = "merge_commit")]
+    pub merge: u64,
+    pub squash: u64,
+    pub rebase: u64,
+    pub unknown: u64,
+}
+
+impl MergeMethods {
+    pub fn add(&mut self, method: MergeMethod) {
+        match method {
+            MergeMethod::Merge => self.merge += 1,
+            MergeMethod::Squash => self.squash += 1,
+            MergeMethod::Rebase => self.rebase += 1,
+            MergeMethod::Unknown => self.unknown += 1,
+        }
+    }
+}
+
+impl Display for MergeMethods {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let total = (self.merge + self.squash + self.rebase + self.unknown).max(1) as f64;
+        writeln!(f, "Merge methods:")?;
+        for (name, count) in [
+            ("merge commit", self.merge),
+            ("squash", self.squash),
+            ("rebase", self.rebase),
+            ("unknown", self.unknown),
+        ] {
+            writeln!(f, "  {}: {} ({:.1}%)", name, count, count as f64 * 100.0 / total)?;
+        }
+        Ok(())
+    }
+}
+
+// Each author's merge dates, bucketed into weeks only when rendered, once
+// the dates the run actually covers are known.
+#[derive(Default)]
+pub struct WeeklyActivity {
+    // Keyed by lowercased login.
+    pub merged: HashMap<String, Vec<NaiveDate>>,
+}
+
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+impl WeeklyActivity {
+    // Unmerged pull requests have no merge week and are left out.
+    pub fn add_pull_request(&mut self, pr: &PullRequest) {
+        if let Some(merged_at) = pr.merged_at {
+            self.merged
+                .entry(pr.author.login.to_lowercase())
+                .or_default()
+                .push(merged_at.date_naive());
+        }
+    }
+
+    // One glyph per week from --since (or the first merge) to --until (or
+    // the last), scaled to the user's busiest week. A week without merges is
+    // the lowest glyph; any merge at all shows at least one step above it.
+    pub fn sparkline(&self, login: &str, span: &DateBounds) -> String {
+        let start = span.requested_since.or(span.earliest.map(|earliest| earliest.date_naive()));
+        let end = span.requested_until.or(span.latest.map(|latest| latest.date_naive()));
+        let (Some(start), Some(end)) = (start, end) else {
+            return String::new();
+        };
+        let mut weeks = vec![0u64; ((end - start).num_days().max(0) / 7 + 1) as usize];
+        for date in self.merged.get(&login.to_lowercase()).into_iter().flatten() {
+            if *date < start {
+                continue;
+            }
+            if let Some(week) = weeks.get_mut(((*date - start).num_days() / 7) as usize) {
+                *week += 1;
+            }
+        }
+        let busiest = weeks.iter().copied().max().unwrap_or_default().max(1);
+        let top = (SPARKS.len() - 1) as u64;
+        weeks
+            .iter()
+            .map(|&count| SPARKS[(count * top).div_ceil(busiest) as usize])
+            .collect()
+    }
+}
+
+pub struct PullRequestRecord {
+    pub repo: String,
+    pub number: u64,
+    pub title: String,
+    pub url: String,
+    pub author: String,
+    pub merged_at: Option<DateTime<Utc>>,
+    pub additions: u64,
+    pub deletions: u64,
+    pub changed_files: u64,
+    pub reviews: u64,
+    pub comments: u64,
+}
+
+impl PullRequestRecord {
+    pub fn new(repo: &str, pr: &PullRequest, unknown_label: &str) -> PullRequestRecord {
+        let author = if pr.author.login == DELETED_LOGIN {
+            unknown_label
+        } else {
+            &pr.author.login
+        };
+        PullRequestRecord {
+            repo: repo.to_string(),
+            number: pr.number,
+            title: pr.title.clone(),
+            url: pr.url.clone(),
+            author: author.to_string(),
+            merged_at: pr.merged_at,
+            additions: pr.additions,
+            deletions: pr.deletions,
+            changed_files: pr.changed_files,
+            reviews: pr.reviews.total_count,
+            comments: pr.comments.total_count,
+        }
+    }
+}
+
+pub fn is_sqlite_path(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|ext| ext.to_str()),
+        Some("sqlite") | Some("db")
+    )
+}
+
+// Sibling of `path` to build the new file in, so the final rename stays on
+// one filesystem. Creates missing parent directories.
+fn temp_path(path: &Path) -> Result<PathBuf> {
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    let name = path
+        .file_name()
+        .ok_or_else(|| anyhow::anyhow!("not a file path: {}", path.display()))?;
+    let mut temp = OsString::from(".");
+    temp.push(name);
+    temp.push(format!(".{}.tmp", std::process::id()));
+    Ok(path.with_file_name(temp))
+}
+
+// Builds the file next to `path` and renames it into place, so a crashed run
+// never leaves a truncated file behind.
+fn replace_file(path: &Path, build: impl FnOnce(&Path) -> Result<()>) -> Result<()> {
+    let temp = temp_path(path)?;
+    if temp.exists() {
+        fs::remove_file(&temp)?;
+    }
+    let result = build(&temp).and_then(|_| Ok(fs::rename(&temp, path)?));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    replace_file(path, |temp| write_new(fs::OpenOptions::new(), temp, contents))
+}
+
+// Like write_atomic, for files only the current user may read, such as
+// cached responses. The mode only applies on Unix.
+pub(crate) fn write_private(path: &Path, contents: &[u8]) -> Result<()> {
+    #[allow(unused_mut)]
+    let mut options = fs::OpenOptions::new();
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    replace_file(path, |temp| write_new(options, temp, contents))
+}
+
+fn write_new(mut options: fs::OpenOptions, path: &Path, contents: &[u8]) -> Result<()> {
+    let mut file = options.write(true).create_new(true).open(path)?;
+    io::Write::write_all(&mut file, contents)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+pub fn write_sqlite(
+    path: &Path,
+    meta: &[(&str, String)],
+    users: &ScoredUser,
+    pull_requests: &[PullRequestRecord],
+) -> Result<()> {
+    replace_file(path, |temp| build_sqlite(temp, meta, users, pull_requests))
+}
+
+fn build_sqlite(
+    path: &Path,
+    meta: &[(&str, String)],
+    users: &ScoredUser,
+    pull_requests: &[PullRequestRecord],
+) -> Result<()> {
+    let mut conn = rusqlite::Connection::open(path)?;
+    let tx = conn.transaction()?;
+    tx.execute_batch(
+        "CREATE TABLE meta (
+            key TEXT PRIMARY KEY,
+            value TEXT
+        );
+        CREATE TABLE users (
+            login TEXT PRIMARY KEY,
+            rank INTEGER NOT NULL,
+            score REAL NOT NULL,
+            approvals INTEGER NOT NULL,
+            requested_changes INTEGER NOT NULL,
+            comments INTEGER NOT NULL,
+            review_comments INTEGER NOT NULL,
+            prs_reviewed INTEGER NOT NULL,
+            pull_requests INTEGER NOT NULL,
+            additions INTEGER NOT NULL,
+            deletions INTEGER NOT NULL,
+            changed_files INTEGER NOT NULL,
+            comment_words INTEGER NOT NULL,
+            reviews_dismissed INTEGER NOT NULL,
+            unblocking_approvals INTEGER NOT NULL,
+            self_merges INTEGER NOT NULL,
+            avg_changed_files_per_pr REAL,
+            normalized_score REAL,
+            review_ratio REAL
+        );
+        CREATE TABLE pull_requests (
+            repo TEXT NOT NULL,
+            number INTEGER NOT NULL,
+            title TEXT NOT NULL,
+            url TEXT NOT NULL,
+            author TEXT NOT NULL,
+            merged_at TEXT,
+            additions INTEGER NOT NULL,
+            deletions INTEGER NOT NULL,
+            changed_files INTEGER NOT NULL,
+            reviews INTEGER NOT NULL,
+            comments INTEGER NOT NULL,
+            PRIMARY KEY (repo, number)
+        );
+        CREATE INDEX idx_users_score ON users (score);
+        CREATE INDEX idx_pull_requests_author ON pull_requests (author);
+        CREATE INDEX idx_pull_requests_merged_at ON pull_requests (merged_at);",
+    )?;
+    {
+        let mut insert = tx.prepare("INSERT INTO meta (key, value) VALUES (?1, ?2)")?;
+        for (key, value) in meta {
+            insert.execute(rusqlite::params![key, value])?;
+        }
+        let mut insert = tx.prepare(
+            "INSERT INTO users VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19)",
+        )?;
+        for (rank, (login, stats)) in users.0.iter().enumerate() {
+            insert.execute(rusqlite::params![
+                login,
+                rank as u64 + 1,
+                stats.score,
+                stats.approvals,
+                stats.requested_changes,
+                stats.comments,
+                stats.review_comments,
+                stats.prs_reviewed,
+                stats.pull_requests,
+                stats.additions,
+                stats.deletions,
+                stats.changed_files,
+                stats.comment_words,
+                stats.reviews_dismissed,
+                stats.unblocking_approvals,
+                stats.self_merges,
+                stats.avg_changed_files_per_pr,
+                stats.normalized_score,
+                stats.review_ratio,
+            ])?;
+        }
+        let mut insert = tx.prepare(
+            "INSERT INTO pull_requests VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        )?;
+        for pr in pull_requests {
+            insert.execute(rusqlite::params![
+                pr.repo,
+                pr.number,
+                pr.title,
+                pr.url,
+                pr.author,
+                pr.merged_at.map(|merged_at| merged_at.to_rfc3339()),
+                pr.additions,
+                pr.deletions,
+                pr.changed_files,
+                pr.reviews,
+                pr.comments,
+            ])?;
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+pub enum Warning {
+    TruncatedReviews {
+        repo: String,
+        number: u64,
+        fetched: u64,
+        total: u64,
+    },
+    TruncatedComments {
+        repo: String,
+        number: u64,
+        fetched: u64,
+        total: u64,
+    },
+    NoAccess {
+        repo: String,
+    },
+}
+
+impl Serialize for Warning {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Warning::TruncatedReviews {
+                repo,
+                number,
+                fetched,
+                total,
+            } => write!(
+                f,
+                "{}#{}: only {} of {} reviews were counted",
+                repo, number, fetched, total
+            ),
+            Warning::TruncatedComments {
+                repo,
+                number,
+                fetched,
+                total,
+            } => write!(
+                f,
+                "{}#{}: only {} of {} comments were counted",
+                repo, number, fetched, total
+            ),
+            Warning::NoAccess { repo } => write!(f, "{}: skipped, the token can't access it", repo),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Warnings(pub Vec<Warning>);
+
+impl Display for Warnings {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.0.is_empty() {
+            return Ok(());
+        }
+        writeln!(f, "Warnings (results are incomplete):")?;
+        for warning in self.0.iter() {
+            writeln!(f, "  - {}", warning)?;
+        }
+        Ok(())
+    }
+}
+
+// Merge dates to include, inclusive at both ends and compared in UTC. None
+// leaves that side open.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DateWindow {
+    pub since: Option<NaiveDate>,
+    pub until: Option<NaiveDate>,
+}
+
+impl DateWindow {
+    pub fn contains(&self, date: NaiveDate) -> bool {
+        self.since.is_none_or(|since| since <= date) && self.until.is_none_or(|until| date <= until)
+    }
+}
+
+// An inclusive range of merge dates, written <start>..<end>.
+#[derive(Debug, Clone, Serialize)]
+pub struct DateRange {
+    pub start: NaiveDate,
+    pub end: NaiveDate,
+}
+
+impl DateRange {
+    fn contains(&self, date: NaiveDate) -> bool {
+        self.start <= date && date <= self.end
+    }
+}
+
+pub fn parse_date_range(s: &str) -> Result<DateRange> {
+    let (start, end) = s
+        .split_once("..")
+        .ok_or_else(|| anyhow::anyhow!("expected <start>..<end>, got '{}'", s))?;
+    let range = DateRange {
+        start: parse_date(start)?,
+        end: parse_date(end)?,
+    };
+    if range.start > range.end {
+        return Err(anyhow::anyhow!("range start {} is after its end {}", range.start, range.end));
+    }
+    Ok(range)
+}
+
+pub fn parse_date(s: &str) -> Result<NaiveDate> {
+    NaiveDate::parse_from_str(s, "%Y-%m-%d").map_err(|e| anyhow::anyhow!(e))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct OrganizationResponse {
+    data: OrgData,
+}
+
+impl OrganizationResponse {
+    fn has_next_page(&self) -> bool {
+        self.data
+            .organization
+            .as_ref()
+            .is_some_and(|organization| organization.repositories.page_info.has_next_page)
+    }
+
+    fn next_cursor(&self) -> String {
+        self.data
+            .organization
+            .as_ref()
+            .map(|organization| organization.repositories.page_info.end_cursor.clone())
+            .unwrap_or_default()
+    }
+
+    fn repositories(&self) -> Vec<String> {
+        self.data
+            .organization
+            .iter()
+            .flat_map(|organization| &organization.repositories.edges)
+            .map(|edge| edge.node.name.clone())
+            .collect()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct EnterpriseResponse {
+    data: EnterpriseData,
+}
+
+impl EnterpriseResponse {
+    fn has_next_page(&self) -> bool {
+        self.data.enterprise.organizations.page_info.has_next_page
+    }
+
+    fn next_cursor(&self) -> String {
+        self.data
+            .enterprise
+            .organizations
+            .page_info
+            .end_cursor
+            .clone()
+    }
+
+    fn organizations(&self) -> Vec<String> {
+        self.data
+            .enterprise
+            .organizations
+            .nodes
+            .iter()
+            .map(|node| node.login.clone())
+            .collect()
+    }
+
+    fn extend(&mut self, other: EnterpriseResponse) {
+        self.data
+            .enterprise
+            .organizations
+            .nodes
+            .extend(other.data.enterprise.organizations.nodes);
+        self.data.enterprise.organizations.page_info =
+            other.data.enterprise.organizations.page_info;
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct EnterpriseData {
+    enterprise: Enterprise,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Enterprise {
+    organizations: Organizations,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Organizations {
+    nodes: Vec<OrganizationNode>,
+    #[serde(rename = "pageInfo")]
+    page_info: PageInfo,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct OrganizationNode {
+    login: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TeamResponse {
+    data: TeamData,
+}
+
+impl TeamResponse {
+    // None when the organization or the team is null.
+    fn members(&self) -> Option<&TeamMembers> {
+        Some(&self.data.organization.as_ref()?.team.as_ref()?.members)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TeamData {
+    organization: Option<TeamOrganization>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TeamOrganization {
+    team: Option<Team>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Team {
+    members: TeamMembers,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TeamMembers {
+    nodes: Vec<User>,
+    #[serde(rename = "pageInfo")]
+    page_info: PageInfo,
+}
+
+#[derive(Debug, Deserialize)]
+struct ViewerResponse {
+    data: ViewerData,
+}
+
+#[derive(Debug, Deserialize)]
+struct ViewerData {
+    viewer: User,
+}
+
+// A user's repositories come back in the same shape under `user`. Null when
+// the owner doesn't exist or the token can't see it.
+#[derive(Debug, Serialize, Deserialize)]
+struct OrgData {
+    #[serde(alias = "user")]
+    organization: Option<Organization>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Organization {
+    repositories: Repositories,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Repositories {
+    edges: Vec<RepositoryEdge>,
+    #[serde(rename = "pageInfo")]
+    page_info: PageInfo,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RepositoryEdge {
+    node: RepositoryNode,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RepositoryNode {
+    name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RepositoryResponse {
+    pub data: Data,
+}
+
+impl RepositoryResponse {
+    // Pages come most recently updated first. A pull request is updated
+    // when it is merged, and it can't be merged before it was opened, so
+    // once a page reaches updates before `floor` nothing further back can
+    // be merged or opened on or after it. Only the lower bound can end
+    // pagination early; pull requests after --until are dropped by trim.
+    fn has_next_page(&self, floor: Option<NaiveDate>) -> bool {
+        let Some(pull_requests) = self.pull_requests() else {
+            return false;
+        };
+        let in_window = match (floor, pull_requests.nodes.last()) {
+            (Some(floor), Some(oldest)) => oldest.updated_at.date_naive() >= floor,
+            _ => true,
+        };
+        in_window && pull_requests.page_info.has_next_page
+    }
+
+    fn next_cursor(&self) -> String {
+        self.pull_requests()
+            .map(|pull_requests| pull_requests.page_info.end_cursor.clone())
+            .unwrap_or_default()
+    }
+
+    fn extend(&mut self, other: RepositoryResponse) {
+        if let (Some(repository), Some(other)) = (&mut self.data.repository, other.data.repository) {
+            repository.pull_requests.nodes.extend(other.pull_requests.nodes);
+            repository.pull_requests.page_info = other.pull_requests.page_info;
+        }
+    }
+
+    fn trim(&mut self, window: DateWindow, excluded: &[DateRange], include_unmerged: bool) {
+        if let Some(pull_requests) = self.pull_requests_mut() {
+            pull_requests.nodes.retain(|pr| {
+                pr.window_date(include_unmerged).is_some_and(|date| {
+                    window.contains(date) && !excluded.iter().any(|range| range.contains(date))
+                })
+            });
+        }
+    }
+
+    // The pull requests fetched so far; None only if the repository
+    // itself came back null.
+    pub fn pull_requests(&self) -> Option<&PullRequests> {
+        self.data.repository.as_ref().map(|repository| &repository.pull_requests)
+    }
+
+    fn pull_requests_mut(&mut self) -> Option<&mut PullRequests> {
+        self.data.repository.as_mut().map(|repository| &mut repository.pull_requests)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Data {
+    // Null when the repository doesn't exist or the token can't see it.
+    pub repository: Option<Repository>,
+}
+
+// The `rateLimit` block every query asks for: what the query cost in points
+// and what is left on the token that ran it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RateLimit {
+    pub cost: u64,
+    pub remaining: u64,
+    #[serde(rename = "resetAt")]
+    pub reset_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+struct RateLimitResponse {
+    data: Option<RateLimitData>,
+}
+
+#[derive(Deserialize)]
+struct RateLimitData {
+    #[serde(rename = "rateLimit")]
+    rate_limit: Option<RateLimit>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Repository {
+    #[serde(rename = "primaryLanguage")]
+    pub primary_language: Option<Language>,
+    #[serde(rename = "pullRequests")]
+    pub pull_requests: PullRequests,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Language {
+    pub name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PullRequests {
+    pub nodes: Vec<PullRequest>,
+    #[serde(rename = "pageInfo")]
+    pub page_info: PageInfo,
+    // Every pull request in the requested states, not just the window.
+    // Defaults to 0 for responses cached before it was queried.
+    #[serde(rename = "totalCount")]
+    #[serde(default)]
+    pub total_count: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PageInfo {
+    #[serde(rename = "endCursor")]
+    #[serde(deserialize_with = "default_on_null")]
+    pub end_cursor: String,
+    #[serde(rename = "hasNextPage")]
+    pub has_next_page: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PullRequest {
+    pub number: u64,
+    pub title: String,
+    pub url: String,
+    // The branch it was opened against.
+    #[serde(rename = "baseRefName")]
+    #[serde(default)]
+    pub base_ref_name: String,
+    pub reviews: Reviews,
+    pub comments: Comments,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime<Utc>,
+    // The order pages come in; any change to the pull request moves it.
+    #[serde(rename = "updatedAt")]
+    pub updated_at: DateTime<Utc>,
+    // Null unless the pull request was merged (see --states).
+    #[serde(rename = "mergedAt")]
+    pub merged_at: Option<DateTime<Utc>>,
+    // Null when unmerged, and for some merges GitHub doesn't attribute, such
+    // as a few auto-merges, or when the account was deleted.
+    #[serde(rename = "mergedBy")]
+    #[serde(default)]
+    pub merged_by: Option<User>,
+    pub additions: u64,
+    pub deletions: u64,
+    #[serde(rename = "changedFiles")]
+    pub changed_files: u64,
+    #[serde(deserialize_with = "default_on_null")]
+    pub author: User,
+    #[serde(rename = "timelineItems")]
+    #[serde(default)]
+    pub timeline_items: TimelineItems,
+    #[serde(rename = "mergeCommit")]
+    #[serde(default)]
+    pub merge_commit: Option<MergeCommit>,
+    #[serde(rename = "reviewDecision")]
+    #[serde(default)]
+    pub review_decision: Option<String>,
+    // body and commits are only fetched with --credit-coauthors.
+    #[serde(default)]
+    pub body: String,
+    #[serde(default)]
+    pub commits: Commits,
+    // Only fetched with --label.
+    #[serde(default)]
+    pub labels: Labels,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Labels {
+    pub nodes: Vec<Label>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Label {
+    pub name: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Commits {
+    pub nodes: Vec<PullRequestCommit>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PullRequestCommit {
+    pub commit: CommitMessage,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CommitMessage {
+    pub message: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MergeCommit {
+    #[serde(rename = "messageHeadline")]
+    pub message_headline: String,
+    pub parents: MergeCommitParents,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MergeCommitParents {
+    #[serde(rename = "totalCount")]
+    pub total_count: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct TimelineItems {
+    pub nodes: Vec<ReviewDismissedEvent>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReviewDismissedEvent {
+    pub review: Option<DismissedReview>,
+    // Set when the review went stale because of a new push, as opposed to
+    // being dismissed by hand.
+    #[serde(rename = "pullRequestCommit")]
+    pub pull_request_commit: Option<CommitRef>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DismissedReview {
+    #[serde(deserialize_with = "default_on_null")]
+    pub author: User,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CommitRef {
+    pub id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Reviews {
+    #[serde(rename = "totalCount")]
+    pub total_count: u64,
+    pub nodes: Vec<Review>,
+    #[serde(rename = "pageInfo")]
+    #[serde(default)]
+    pub page_info: Option<PageInfo>,
+}
+
+impl Reviews {
+    fn next_cursor(&self) -> Option<String> {
+        next_cursor(&self.page_info)
+    }
+
+    fn extend(&mut self, other: Reviews) {
+        self.nodes.extend(other.nodes);
+        self.page_info = other.page_info;
+    }
+}
+
+impl PullRequest {
+    // What deleted accounts contributed here, such as "author, 2 reviews",
+    // so the unattributable totals can be traced back to pull requests.
+    pub fn deleted_contributions(&self) -> Vec<String> {
+        let mut contributions = Vec::new();
+        if self.author.login == DELETED_LOGIN {
+            contributions.push("author".to_string());
+        }
+        let reviews = self
+            .reviews
+            .nodes
+            .iter()
+            .filter(|review| review.author.login == DELETED_LOGIN)
+            .count();
+        if reviews > 0 {
+            contributions.push(format!("{} reviews", reviews));
+        }
+        let comments = self
+            .comments
+            .nodes
+            .iter()
+            .filter(|comment| comment.author.login == DELETED_LOGIN)
+            .count();
+        if comments > 0 {
+            contributions.push(format!("{} comments", comments));
+        }
+        contributions
+    }
+
+    pub fn discussion_volume(&self) -> u64 {
+        let review_comments: u64 = self
+            .reviews
+            .nodes
+            .iter()
+            .map(|review| review.comments.total_count)
+            .sum();
+        let commented_reviews = self
+            .reviews
+            .nodes
+            .iter()
+            .filter(|review| review.state == "COMMENTED")
+            .count() as u64;
+        commented_reviews + self.comments.total_count + review_comments
+    }
+
+    // Time from opening the PR to each reviewer's first approval, in the
+    // order the approvals were submitted.
+    fn approval_latencies(&self) -> Vec<(String, chrono::Duration)> {
+        let mut approvals: Vec<(String, DateTime<Utc>)> = Vec::new();
+        for review in self.reviews.nodes.iter() {
+            if review.state != "APPROVED" {
+                continue;
+            }
+            if let Some(submitted_at) = review.submitted_at {
+                if !approvals.iter().any(|(login, _)| *login == review.author.login) {
+                    approvals.push((review.author.login.clone(), submitted_at));
+                }
+            }
+        }
+        approvals.sort_by_key(|(_, submitted_at)| *submitted_at);
+        approvals
+            .into_iter()
+            .map(|(login, submitted_at)| (login, submitted_at - self.created_at))
+            .collect()
+    }
+
+    // Merged by its own author. Unknown mergers and deleted authors never
+    // count.
+    pub fn is_self_merge(&self) -> bool {
+        self.author.login != DELETED_LOGIN
+            && self
+                .merged_by
+                .as_ref()
+                .is_some_and(|merger| merger.login.eq_ignore_ascii_case(&self.author.login))
+    }
+
+    // When it was merged, or opened if it wasn't, which is what
+    // --decay-half-life measures its age from.
+    pub fn activity_date(&self) -> DateTime<Utc> {
+        self.merged_at.unwrap_or(self.created_at)
+    }
+
+    // The date the window applies to: when it was merged, or, if unmerged
+    // states were asked for, when it was opened. None for an unmerged pull
+    // request in a merged-only run, e.g. a draft GitHub returned anyway,
+    // which no window contains.
+    pub fn window_date(&self, include_unmerged: bool) -> Option<NaiveDate> {
+        match self.merged_at {
+            Some(merged_at) => Some(merged_at.date_naive()),
+            None if include_unmerged => Some(self.created_at.date_naive()),
+            None => None,
+        }
+    }
+
+    // Time from opening to the earliest submitted review by anyone but the
+    // author; None if nobody else reviewed it.
+    fn first_review_latency(&self) -> Option<chrono::Duration> {
+        self.reviews
+            .nodes
+            .iter()
+            .filter(|review| review.author.login != self.author.login)
+            .filter_map(|review| review.submitted_at)
+            .min()
+            .map(|submitted_at| submitted_at - self.created_at)
+    }
+
+    // GitHub doesn't record how a PR was merged, so it is inferred from the
+    // merge commit: two parents means a merge commit, a headline ending in
+    // GitHub's default squash suffix "(#<number>)" means a squash, and any
+    // other single-parent commit is taken to be a rebase. A squash whose
+    // message was edited to drop the suffix is therefore counted as a rebase.
+    pub fn merge_method(&self) -> MergeMethod {
+        match &self.merge_commit {
+            None => MergeMethod::Unknown,
+            Some(commit) if commit.parents.total_count > 1 => MergeMethod::Merge,
+            Some(commit)
+                if commit
+                    .message_headline
+                    .trim_end()
+                    .ends_with(&format!("(#{})", self.number)) =>
+            {
+                MergeMethod::Squash
+            }
+            Some(_) => MergeMethod::Rebase,
+        }
+    }
+
+    // Reviews whose state is tallied. With `latest_only`, each reviewer's
+    // reviews collapse to the one that stands: their last review that isn't a
+    // plain comment, or their last comment if that's all they left. Deleted
+    // accounts share a login, so their reviews are never collapsed.
+    pub fn tallied_reviews(&self, latest_only: bool) -> Vec<&Review> {
+        let reviews = &self.reviews.nodes;
+        if !latest_only {
+            return reviews.iter().collect();
+        }
+        let mut standing: HashMap<&str, usize> = HashMap::new();
+        let mut kept = Vec::new();
+        for (i, review) in reviews.iter().enumerate() {
+            let login = review.author.login.as_str();
+            if login == DELETED_LOGIN {
+                kept.push(i);
+                continue;
+            }
+            let current = standing.entry(login).or_insert(i);
+            if review.state != "COMMENTED" || reviews[*current].state == "COMMENTED" {
+                *current = i;
+            }
+        }
+        kept.extend(standing.into_values());
+        kept.sort_unstable();
+        kept.into_iter().map(|i| &reviews[i]).collect()
+    }
+
+    // The reviewer whose approval was the last one submitted before the merge,
+    // on PRs where branch protection required approval (reviewDecision is
+    // APPROVED). That approval is assumed to be the one that unblocked the
+    // merge. review_decision is only fetched with --credit-unblocking.
+    fn unblocking_reviewer(&self) -> Option<&str> {
+        if self.review_decision.as_deref() != Some("APPROVED") {
+            return None;
+        }
+        let merged_at = self.merged_at?;
+        self.reviews
+            .nodes
+            .iter()
+            .filter(|review| review.state == "APPROVED")
+            .filter_map(|review| Some((review.submitted_at?, review)))
+            .filter(|(submitted_at, _)| *submitted_at <= merged_at)
+            .max_by_key(|(submitted_at, _)| *submitted_at)
+            .map(|(_, review)| review.author.login.as_str())
+    }
+
+    // The author, then everyone named in a Co-authored-by trailer of the
+    // description or a commit message, each once.
+    pub fn contributors(&self) -> Vec<String> {
+        let mut contributors = vec![self.author.login.clone()];
+        let messages = self.commits.nodes.iter().map(|node| node.commit.message.as_str());
+        for text in std::iter::once(self.body.as_str()).chain(messages) {
+            for login in coauthor_trailers(text) {
+                if !contributors.iter().any(|known| known.eq_ignore_ascii_case(&login)) {
+                    contributors.push(login);
+                }
+            }
+        }
+        contributors
+    }
+
+    // Whether it carries any of the labels, matched regardless of case as
+    // GitHub does.
+    pub fn has_any_label(&self, names: &[String]) -> bool {
+        self.labels
+            .nodes
+            .iter()
+            .any(|label| names.iter().any(|name| name.eq_ignore_ascii_case(&label.name)))
+    }
+
+    pub fn truncation_warnings(&self, repo: &str) -> Vec<Warning> {
+        let mut warnings = Vec::new();
+        let reviews = self.reviews.nodes.len() as u64;
+        if reviews < self.reviews.total_count {
+            warnings.push(Warning::TruncatedReviews {
+                repo: repo.to_string(),
+                number: self.number,
+                fetched: reviews,
+                total: self.reviews.total_count,
+            });
+        }
+        let comments = self.comments.nodes.len() as u64;
+        if comments < self.comments.total_count {
+            warnings.push(Warning::TruncatedComments {
+                repo: repo.to_string(),
+                number: self.number,
+                fetched: comments,
+                total: self.comments.total_count,
+            });
+        }
+        warnings
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Review {
+    #[serde(deserialize_with = "default_on_null")]
+    pub author: User,
+    pub state: String,
+    #[serde(rename = "submittedAt")]
+    pub submitted_at: Option<DateTime<Utc>>,
+    pub comments: ReviewComments,
+    #[serde(rename = "bodyText")]
+    #[serde(default)]
+    pub body_text: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReviewComments {
+    #[serde(rename = "totalCount")]
+    pub total_count: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Comments {
+    #[serde(rename = "totalCount")]
+    pub total_count: u64,
+    pub nodes: Vec<Comment>,
+    #[serde(rename = "pageInfo")]
+    #[serde(default)]
+    pub page_info: Option<PageInfo>,
+}
+
+impl Comments {
+    fn next_cursor(&self) -> Option<String> {
+        next_cursor(&self.page_info)
+    }
+
+    fn extend(&mut self, other: Comments) {
+        self.nodes.extend(other.nodes);
+        self.page_info = other.page_info;
+    }
+}
+
+// The cursor of the next page of a pull request's reviews or comments, if
+// there is one.
+fn next_cursor(page_info: &Option<PageInfo>) -> Option<String> {
+    page_info
+        .as_ref()
+        .filter(|page_info| page_info.has_next_page)
+        .map(|page_info| page_info.end_cursor.clone())
+}
+
+// Follow-up page of one pull request's reviews or comments.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PullRequestPageResponse {
+    pub data: PullRequestPageData,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PullRequestPageData {
+    pub repository: PullRequestPageRepository,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PullRequestPageRepository {
+    #[serde(rename = "pullRequest")]
+    pub pull_request: PullRequestPage,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PullRequestPage {
+    #[serde(default)]
+    pub reviews: Option<Reviews>,
+    #[serde(default)]
+    pub comments: Option<Comments>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Comment {
+    #[serde(deserialize_with = "default_on_null")]
+    pub author: User,
+    #[serde(rename = "bodyText")]
+    #[serde(default)]
+    pub body_text: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct User {
+    pub login: String,
+}
+
+// Stands in for the login of deleted accounts. Angle brackets can't appear
+// in a GitHub login, so it can't collide with a real user.
+pub const DELETED_LOGIN: &str = "<deleted>";
+
+impl Default for User {
+    fn default() -> User {
+        User {
+            login: DELETED_LOGIN.to_string(),
+        }
+    }
+}
+
+// Every field is optional in the JSON output, since --fields picks them.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct UserStats {
+    // As first seen; GitHubUsers keys on the lowercased form.
+    #[serde(skip)]
+    pub login: String,
+    pub approvals: u64,
+    pub requested_changes: u64,
+    pub comments: u64,
+    pub review_comments: u64,
+    // Pull requests they submitted at least one review on, however many.
+    pub prs_reviewed: u64,
+    pub pull_requests: u64,
+    pub additions: u64,
+    pub deletions: u64,
+    pub changed_files: u64,
+    pub comment_words: u64,
+    pub reviews_dismissed: u64,
+    pub unblocking_approvals: u64,
+    // Authored pull requests they merged themselves.
+    pub self_merges: u64,
+    pub avg_changed_files_per_pr: Option<f64>,
+    pub review_ratio: Option<f64>,
+    // Unrounded; output rounds it to two decimals.
+    pub score: f64,
+    // Only shown with --explain or --fields breakdown.
+    pub breakdown: ScoreBreakdown,
+    pub normalized_score: Option<f64>,
+    // Each authored PR's size, for the non-linear --size-scoring modes.
+    #[serde(skip)]
+    pub pull_request_sizes: Vec<PullRequestSize>,
+    // Review credit per pull request reviewed, for --size-weighted-reviews.
+    #[serde(skip)]
+    pub review_credits: Vec<ReviewCredit>,
+}
+
+impl UserStats {
+    // Reviews and comments given, the top of the review ratio.
+    fn reviews(&self) -> u64 {
+        self.approvals + self.comments + self.requested_changes
+    }
+
+    fn has_reviewed(&self) -> bool {
+        self.approvals + self.requested_changes + self.comments + self.review_comments > 0
+    }
+
+    pub fn new() -> UserStats {
+        UserStats {
+            login: String::new(),
+            approvals: 0,
+            requested_changes: 0,
+            comments: 0,
+            review_comments: 0,
+            prs_reviewed: 0,
+            pull_requests: 0,
+            additions: 0,
+            deletions: 0,
+            changed_files: 0,
+            comment_words: 0,
+            reviews_dismissed: 0,
+            unblocking_approvals: 0,
+            self_merges: 0,
+            avg_changed_files_per_pr: None,
+            review_ratio: None,
+            score: 0.0,
+            breakdown: ScoreBreakdown::default(),
+            normalized_score: None,
+            pull_request_sizes: Vec::new(),
+            review_credits: Vec::new(),
+        }
+    }
+
+    fn add_counts(&mut self, other: &UserStats) {
+        self.approvals += other.approvals;
+        self.requested_changes += other.requested_changes;
+        self.comments += other.comments;
+        self.review_comments += other.review_comments;
+        self.prs_reviewed += other.prs_reviewed;
+        self.pull_requests += other.pull_requests;
+        self.additions += other.additions;
+        self.deletions += other.deletions;
+        self.changed_files += other.changed_files;
+        self.comment_words += other.comment_words;
+        self.reviews_dismissed += other.reviews_dismissed;
+        self.unblocking_approvals += other.unblocking_approvals;
+        self.self_merges += other.self_merges;
+    }
+}
+
+// Bodies longer than this are usually pasted logs or generated output
+// rather than review effort, so they are capped.
+const MAX_WORDS_PER_COMMENT: u64 = 1000;
+
+fn word_count(body: &Option<String>) -> u64 {
+    match body {
+        Some(body) => (body.split_whitespace().count() as u64).min(MAX_WORDS_PER_COMMENT),
+        None => 0,
+    }
+}
+
+// Logins named by the Co-authored-by trailers in a description or commit
+// message. Only GitHub's noreply addresses say which account they belong
+// to; any other address is counted as unattributable.
+fn coauthor_trailers(text: &str) -> Vec<String> {
+    text.lines()
+        .filter_map(|line| {
+            let (key, value) = line.trim().split_once(':')?;
+            if !key.trim().eq_ignore_ascii_case("co-authored-by") {
+                return None;
+            }
+            let (_, email) = value.rsplit_once('<')?;
+            let email = email.strip_suffix('>')?.trim();
+            Some(noreply_login(email).unwrap_or(DELETED_LOGIN).to_string())
+        })
+        .collect()
+}
+
+// The login in <id>+<login>@users.noreply.github.com, or in the older
+// <login>@users.noreply.github.com form.
+fn noreply_login(email: &str) -> Option<&str> {
+    let (local, domain) = email.rsplit_once('@')?;
+    if !domain.eq_ignore_ascii_case("users.noreply.github.com") {
+        return None;
+    }
+    let login = local.split_once('+').map_or(local, |(_, login)| login);
+    (!login.is_empty()).then_some(login)
+}
+
+fn default_on_null<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+where
+    T: Deserialize<'de> + Default,
+    D: serde::Deserializer<'de>,
+{
+    Deserialize::deserialize(deserializer).map(|x: Option<T>| x.unwrap_or_default())
+}
+
+// Renders a pagination cursor as a GraphQL argument value: `null` for the
+// first page, otherwise a quoted string literal. GraphQL string escapes are
+// a superset of JSON's, so serde_json does the escaping.
+fn graphql_cursor(after: &Option<String>) -> String {
+    match after {
+        Some(cursor) => serde_json::Value::String(cursor.clone()).to_string(),
+        None => "null".to_string(),
+    }
+}
+
+struct TokenState {
+    token: String,
+    remaining: Option<u64>,
+    reset_at: Option<i64>,
+    invalid: bool,
+}
+
+impl TokenState {
+    // `reserve` is the quota held back: as many requests as can be in flight
+    // at once, so a token counts as exhausted before it actually hits zero.
+    fn is_exhausted(&self, now: i64, reserve: u64) -> bool {
+        self.remaining.is_some_and(|remaining| remaining <= reserve)
+            && self.reset_at.is_some_and(|reset_at| reset_at > now)
+    }
+}
+
+enum Acquired {
+    Token(usize, String),
+    // Every usable token is exhausted until this Unix timestamp.
+    WaitUntil(i64),
+}
+
+// Spreads requests over one or more tokens, each with its own rate limit.
+// Every request goes to the usable token with the most remaining quota,
+// round-robin among ties, so one token draining routes traffic to the others.
+struct TokenPool {
+    tokens: Mutex<Vec<TokenState>>,
+    next: AtomicUsize,
+}
+
+impl TokenPool {
+    fn new(tokens: Vec<String>) -> TokenPool {
+        TokenPool {
+            tokens: Mutex::new(
+                tokens
+                    .into_iter()
+                    .map(|token| TokenState {
+                        token,
+                        remaining: None,
+                        reset_at: None,
+                        invalid: false,
+                    })
+                    .collect(),
+            ),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    // Waits for the earliest reset when every usable token is exhausted.
+    async fn acquire(&self, reserve: u64) -> Result<(usize, String)> {
+        loop {
+            let reset_at = match self.try_acquire(reserve)? {
+                Acquired::Token(i, token) => return Ok((i, token)),
+                Acquired::WaitUntil(reset_at) => reset_at,
+            };
+            let wait = (reset_at - Utc::now().timestamp()).max(0) as u64 + 1;
+            tracing::warn!(
+                "Rate limit exhausted on every GitHub token, sleeping {}s until {}",
+                wait,
+                DateTime::from_timestamp(reset_at, 0).unwrap_or_default()
+            );
+            tokio::time::sleep(tokio::time::Duration::from_secs(wait)).await;
+        }
+    }
+
+    fn try_acquire(&self, reserve: u64) -> Result<Acquired> {
+        let tokens = self.tokens.lock().unwrap();
+        let now = Utc::now().timestamp();
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let mut best: Option<usize> = None;
+        for offset in 0..tokens.len() {
+            let i = (start + offset) % tokens.len();
+            let state = &tokens[i];
+            if state.invalid || state.is_exhausted(now, reserve) {
+                continue;
+            }
+            let remaining = state.remaining.unwrap_or(u64::MAX);
+            if best.is_none_or(|b| remaining > tokens[b].remaining.unwrap_or(u64::MAX)) {
+                best = Some(i);
+            }
+        }
+        match best {
+            Some(i) => Ok(Acquired::Token(i, tokens[i].token.clone())),
+            None if tokens.iter().all(|state| state.invalid) => {
+                Err(anyhow::anyhow!("no valid GitHub token left: all were rejected with 401"))
+            }
+            None => tokens
+                .iter()
+                .filter(|state| !state.invalid)
+                .filter_map(|state| state.reset_at)
+                .min()
+                .map(Acquired::WaitUntil)
+                .ok_or_else(|| anyhow::anyhow!("rate limit exhausted with no reset time")),
+        }
+    }
+
+    // Total quota left across usable tokens, if GitHub has reported any.
+    fn remaining(&self) -> Option<u64> {
+        let tokens = self.tokens.lock().unwrap();
+        tokens
+            .iter()
+            .filter(|state| !state.invalid)
+            .filter_map(|state| state.remaining)
+            .reduce(|a, b| a + b)
+    }
+
+    fn update(&self, i: usize, headers: &reqwest::header::HeaderMap) {
+        let header = |name: &str| -> Option<i64> { headers.get(name)?.to_str().ok()?.parse().ok() };
+        let mut tokens = self.tokens.lock().unwrap();
+        if let Some(remaining) = header("x-ratelimit-remaining") {
+            tokens[i].remaining = Some(remaining.max(0) as u64);
+        }
+        if let Some(reset_at) = header("x-ratelimit-reset") {
+            tokens[i].reset_at = Some(reset_at);
+        }
+    }
+
+    fn invalidate(&self, i: usize) {
+        self.tokens.lock().unwrap()[i].invalid = true;
+    }
+
+    fn all(&self) -> Vec<String> {
+        self.tokens.lock().unwrap().iter().map(|state| state.token.clone()).collect()
+    }
+}
+
+pub const GITHUB_GRAPHQL_URL: &str = "https://api.github.com/graphql";
+
+// Sent with every request, GraphQL and REST alike, unless overridden.
+pub const DEFAULT_USER_AGENT: &str = concat!("git-stats/", env!("CARGO_PKG_VERSION"));
+
+// GitHub Actions sets GITHUB_API_URL to the REST root (https://api.github.com
+// or https://<host>/api/v3), so those are mapped to their GraphQL endpoint.
+pub fn graphql_url(api_url: &str) -> Result<reqwest::Url> {
+    let mut url = reqwest::Url::parse(api_url)?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(anyhow::anyhow!("API URL must be http or https, got {}", api_url));
+    }
+    let path = url.path().trim_end_matches('/').to_string();
+    if path.is_empty() {
+        url.set_path("/graphql");
+    } else if let Some(prefix) = path.strip_suffix("/v3") {
+        url.set_path(&format!("{}/graphql", prefix));
+    }
+    Ok(url)
+}
+
+// The HTTP client for every request, GraphQL and REST alike. Proxies come
+// from HTTPS_PROXY, HTTP_PROXY and NO_PROXY as usual. `ca_cert` is a PEM
+// file of extra root certificates, for Enterprise Server behind an internal
+// CA; `insecure` skips certificate verification altogether. `timeout` bounds
+// a whole request, body included, and connecting gets at most ten seconds of
+// it.
+pub fn http_client(timeout: std::time::Duration, insecure: bool, ca_cert: Option<&Path>) -> Result<Client> {
+    let mut builder = Client::builder()
+        .timeout(timeout)
+        .connect_timeout(timeout.min(std::time::Duration::from_secs(10)));
+    if let Some(path) = ca_cert {
+        let pem = fs::read(path).map_err(|e| anyhow::anyhow!("can't read {}: {}", path.display(), e))?;
+        let certificates = reqwest::Certificate::from_pem_bundle(&pem)
+            .map_err(|e| anyhow::anyhow!("invalid CA certificate in {}: {}", path.display(), e))?;
+        if certificates.is_empty() {
+            return Err(anyhow::anyhow!("no PEM certificate found in {}", path.display()));
+        }
+        for certificate in certificates {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+    if insecure {
+        tracing::warn!("TLS certificate verification is disabled");
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+    Ok(builder.build()?)
+}
+
+// Exponential backoff from half a second, capped at 30 seconds, plus up to
+// 50% jitter so concurrent tasks don't retry in lockstep.
+fn backoff(attempt: u32) -> std::time::Duration {
+    let base = (500u64 << attempt.min(6)).min(30_000);
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.subsec_nanos() as u64)
+        .unwrap_or(0);
+    std::time::Duration::from_millis(base + nanos % (base / 2 + 1))
+}
+
+// GitHub's secondary rate limits, on concurrent or rapid requests, answer
+// 403 or 429 with a message saying so; a 403 for missing permissions
+// doesn't mention it. Retry-After gives the seconds to wait, and without it
+// GitHub asks for at least a minute.
+fn secondary_rate_limit_wait(
+    status: reqwest::StatusCode,
+    retry_after: Option<u64>,
+    body: &str,
+) -> Option<std::time::Duration> {
+    if !matches!(status.as_u16(), 403 | 429) {
+        return None;
+    }
+    let body = body.to_lowercase();
+    if !body.contains("secondary rate limit") && !body.contains("abuse detection") {
+        return None;
+    }
+    Some(std::time::Duration::from_secs(retry_after.unwrap_or(60)))
+}
+
+// GitHub's GraphQL API answers 500 for the odd internal error that goes away
+// on its own, as well as the gateway errors.
+fn is_retryable_status(status: reqwest::StatusCode) -> bool {
+    matches!(status.as_u16(), 500 | 502..=504)
+}
+
+// A success whose body was cut off on the way, e.g. by a proxy.
+fn is_malformed(body: &str) -> bool {
+    serde_json::from_str::<serde::de::IgnoredAny>(body).is_err()
+}
+
+// Failures reported inside an otherwise successful GraphQL response, split
+// by what the user should do about them.
+#[derive(Debug)]
+pub enum ApiError {
+    RateLimited(String),
+    NotFound(String),
+    // A null owner or repository without any error attached, e.g.
+    // ("organization", "foo").
+    Inaccessible(&'static str, String),
+    GraphQl { kind: String, message: String },
+    Parse { error: serde_json::Error, body: String },
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiError::RateLimited(message) => {
+                write!(f, "rate limited by GitHub, retry after the limit resets: {}", message)
+            }
+            ApiError::NotFound(message) => write!(
+                f,
+                "not found, check the names and that the token can access them: {}",
+                message
+            ),
+            ApiError::Inaccessible(kind, name) => {
+                write!(f, "{} '{}' not found or not accessible", kind, name)
+            }
+            ApiError::GraphQl { kind, message } => write!(f, "GitHub API error ({}): {}", kind, message),
+            ApiError::Parse { error, body } => {
+                write!(f, "unexpected response from GitHub ({}): {}", error, body)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+#[derive(Deserialize)]
+struct GraphQlErrors {
+    #[serde(default)]
+    errors: Vec<GraphQlError>,
+}
+
+#[derive(Deserialize)]
+struct GraphQlError {
+    message: String,
+    #[serde(rename = "type")]
+    kind: Option<String>,
+}
+
+// GraphQL reports errors with a 200 status and an `errors` array, which
+// is checked before the body is parsed as T.
+fn parse_response<T: serde::de::DeserializeOwned>(raw: &str) -> Result<T> {
+    if let Ok(GraphQlErrors { errors }) = serde_json::from_str(raw) {
+        if let Some(first) = errors.first() {
+            let message = errors
+                .iter()
+                .map(|error| error.message.as_str())
+                .collect::<Vec<_>>()
+                .join("; ");
+            let error = match first.kind.as_deref() {
+                Some("RATE_LIMITED") => ApiError::RateLimited(message),
+                Some("NOT_FOUND") => ApiError::NotFound(message),
+                kind => ApiError::GraphQl {
+                    kind: kind.unwrap_or("UNKNOWN").to_string(),
+                    message,
+                },
+            };
+            return Err(error.into());
+        }
+    }
+    serde_json::from_str(raw).map_err(|error| {
+        ApiError::Parse {
+            error,
+            body: raw.to_string(),
+        }
+        .into()
+    })
+}
+
+// Whether --owner names an organization or a personal account.
+#[derive(Debug, Default, Clone, Copy, PartialEq, ValueEnum, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OwnerType {
+    /// Try the organization first, then fall back to a user
+    #[default]
+    Auto,
+    /// An organization
+    Org,
+    /// A personal account; only repositories it owns are scanned
+    User,
+}
+
+// Pull request states the stats query asks for.
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+#[value(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum PullRequestState {
+    Merged,
+    Open,
+    /// Closed without being merged
+    Closed,
+}
+
+impl PullRequestState {
+    fn as_graphql(&self) -> &'static str {
+        match self {
+            PullRequestState::Merged => "MERGED",
+            PullRequestState::Open => "OPEN",
+            PullRequestState::Closed => "CLOSED",
+        }
+    }
+}
+
+// Optional parts of the pull request query. Each one makes responses larger.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct QueryOptions {
+    pub comment_words: bool,
+    pub dismissals: bool,
+    pub merge_methods: bool,
+    pub unblocking: bool,
+    pub coauthors: bool,
+    pub labels: bool,
+}
+
+// Everything is in repo name order rather than the order the fetches
+// finished, so folding the results in and logging them is reproducible.
+pub struct Fetched {
+    pub repositories: BTreeMap<String, RepositoryResponse>,
+    // Repos that couldn't be fully fetched, with the error for each.
+    pub failures: Vec<(String, anyhow::Error)>,
+    // How each repo went.
+    pub statuses: Vec<(String, RepositoryStatus)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RepositoryStatus {
+    // With the number of pull requests kept in the window.
+    Fetched(u64),
+    // No pull requests in the window.
+    Empty,
+    // Null or NOT_FOUND/FORBIDDEN for this token: archived away, restricted
+    // or private. Skipped rather than counted as a failure, but only for
+    // repositories found by listing the owner's; one asked for by name
+    // that can't be read is an error.
+    AccessDenied,
+    Error(String),
+}
+
+impl RepositoryStatus {
+    // A failed fetch is only access denied when the repository was
+    // discovered and the error says no more than that the token can't see it.
+    fn of_error(error: &anyhow::Error, discovered: bool) -> RepositoryStatus {
+        match error.downcast_ref::<ApiError>() {
+            Some(ApiError::NotFound(_) | ApiError::Inaccessible(..)) if discovered => RepositoryStatus::AccessDenied,
+            Some(ApiError::GraphQl { kind, .. }) if discovered && kind == "FORBIDDEN" => {
+                RepositoryStatus::AccessDenied
+            }
+            _ => RepositoryStatus::Error(format!("{:#}", error)),
+        }
+    }
+}
+
+impl Fetched {
+    // e.g. "12 fetched, 3 empty (no pull requests in the window), 1 skipped
+    // (no access), 1 failed".
+    pub fn status_summary(&self) -> String {
+        let count = |wanted: fn(&RepositoryStatus) -> bool| {
+            self.statuses.iter().filter(|(_, status)| wanted(status)).count()
+        };
+        format!(
+            "{} fetched, {} empty (no pull requests in the window), {} skipped (no access), {} failed",
+            count(|status| matches!(status, RepositoryStatus::Fetched(_))),
+            count(|status| *status == RepositoryStatus::Empty),
+            count(|status| *status == RepositoryStatus::AccessDenied),
+            count(|status| matches!(status, RepositoryStatus::Error(_))),
+        )
+    }
+}
+
+// Hands discovered repositories over to fetch_all as they arrive, dropping
+// those that don't match --repo-pattern. Finishing or dropping it closes the
+// channel, which lets fetch_all finish.
+struct RepositoryQueue<'a> {
+    sender: mpsc::UnboundedSender<(String, String)>,
+    bar: &'a ProgressBar,
+    pattern: Option<&'a RepoPattern>,
+    discovered: usize,
+    queued: usize,
+}
+
+impl<'a> RepositoryQueue<'a> {
+    fn new(
+        sender: mpsc::UnboundedSender<(String, String)>,
+        bar: &'a ProgressBar,
+        pattern: Option<&'a RepoPattern>,
+    ) -> RepositoryQueue<'a> {
+        RepositoryQueue {
+            sender,
+            bar,
+            pattern,
+            discovered: 0,
+            queued: 0,
+        }
+    }
+
+    fn push(&mut self, owner: &str, repos: Vec<String>) {
+        for repo in repos {
+            self.discovered += 1;
+            if self.pattern.is_some_and(|pattern| !pattern.matches(&repo)) {
+                continue;
+            }
+            self.queued += 1;
+            self.bar.inc_length(1);
+            // Only fails once fetch_all has given up, e.g. with --fail-fast.
+            let _ = self.sender.unbounded_send((owner.to_string(), repo));
+        }
+    }
+
+    fn finish(self) {
+        if let Some(pattern) = self.pattern {
+            tracing::info!("{} of {} repositories match {}", self.queued, self.discovered, pattern);
+        }
+    }
+}
+
+// Glob over repository names, e.g. frontend-*. Case-insensitive unless asked
+// otherwise, like GitHub's own repository names.
+pub struct RepoPattern {
+    pattern: glob::Pattern,
+    case_sensitive: bool,
+}
+
+impl RepoPattern {
+    pub fn new(pattern: &str, case_sensitive: bool) -> Result<RepoPattern> {
+        Ok(RepoPattern {
+            pattern: glob::Pattern::new(pattern)?,
+            case_sensitive,
+        })
+    }
+
+    pub fn matches(&self, repo: &str) -> bool {
+        let options = glob::MatchOptions {
+            case_sensitive: self.case_sensitive,
+            ..glob::MatchOptions::new()
+        };
+        self.pattern.matches_with(repo, options)
+    }
+}
+
+impl Display for RepoPattern {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.pattern)
+    }
+}
+
+// How a Fetcher fetches, fixed when it is built.
+pub struct FetcherConfig {
+    // Sends the GraphQL requests; usually the Client from http_client.
+    pub transport: Box<dyn Transport>,
+    pub max_retries: u32,
+    // Repositories fetched at the same time.
+    pub concurrency: usize,
+    // Show a progress bar on stderr while fetching, when it is a terminal.
+    pub progress: bool,
+    // Abort on the first repo that fails instead of recording it and going on.
+    pub fail_fast: bool,
+    pub cache: Option<ResponseCache>,
+    pub repository_lists: Option<RepositoryListCache>,
+    pub options: QueryOptions,
+    pub exclude_ranges: Vec<DateRange>,
+    pub repo_pattern: Option<RepoPattern>,
+    pub owner_type: OwnerType,
+    pub states: Vec<PullRequestState>,
+    // Only pull requests into this branch, filtered by GitHub.
+    pub base_branch: Option<String>,
+    // Stop paginating past pull requests opened before this, as with
+    // --max-pr-age-days. Doesn't drop them; UserFilter::opened_since does.
+    pub opened_since: Option<NaiveDate>,
+    pub user_agent: String,
+}
+
+impl Default for FetcherConfig {
+    fn default() -> FetcherConfig {
+        FetcherConfig {
+            transport: Box::new(Client::new()),
+            max_retries: 3,
+            concurrency: 5,
+            progress: false,
+            fail_fast: false,
+            cache: None,
+            repository_lists: None,
+            options: QueryOptions::default(),
+            exclude_ranges: Vec::new(),
+            repo_pattern: None,
+            owner_type: OwnerType::Auto,
+            states: vec![PullRequestState::Merged],
+            base_branch: None,
+            opened_since: None,
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+        }
+    }
+}
+
+// Fetches merged pull requests from the GitHub GraphQL API, spreading
+// requests over a pool of tokens.
+pub struct Fetcher {
+    transport: Box<dyn Transport>,
+    url: reqwest::Url,
+    tokens: TokenPool,
+    // Every token in the pool, sorted, to key cached responses by the set of
+    // credentials that could have served them.
+    credentials: String,
+    max_retries: u32,
+    concurrency: usize,
+    progress: bool,
+    fail_fast: bool,
+    cache: Option<ResponseCache>,
+    repository_lists: Option<RepositoryListCache>,
+    options: QueryOptions,
+    exclude_ranges: Vec<DateRange>,
+    repo_pattern: Option<RepoPattern>,
+    owner_type: OwnerType,
+    states: Vec<PullRequestState>,
+    base_branch: Option<String>,
+    opened_since: Option<NaiveDate>,
+    user_agent: String,
+    // Rate-limit points spent so far, as reported by each query.
+    cost: AtomicU64,
+}
+
+impl Fetcher {
+    pub fn new(tokens: Vec<String>, api_url: &str, config: FetcherConfig) -> Result<Fetcher> {
+        if tokens.is_empty() {
+            return Err(anyhow::anyhow!("at least one GitHub token is required"));
+        }
+        let mut credentials = tokens.clone();
+        credentials.sort();
+        Ok(Fetcher {
+            transport: config.transport,
+            url: graphql_url(api_url)?,
+            credentials: credentials.join("\n"),
+            tokens: TokenPool::new(tokens),
+            max_retries: config.max_retries,
+            concurrency: config.concurrency,
+            progress: config.progress,
+            fail_fast: config.fail_fast,
+            cache: config.cache,
+            repository_lists: config.repository_lists,
+            options: config.options,
+            exclude_ranges: config.exclude_ranges,
+            repo_pattern: config.repo_pattern,
+            owner_type: config.owner_type,
+            states: config.states,
+            base_branch: config.base_branch,
+            opened_since: config.opened_since,
+            user_agent: config.user_agent,
+            cost: AtomicU64::new(0),
+        })
+    }
+
+    // Total rate-limit quota left across the token pool, once GitHub has
+    // reported it.
+    pub fn remaining(&self) -> Option<u64> {
+        self.tokens.remaining()
+    }
+
+    // Rate-limit points the queries sent so far have cost. Cached responses
+    // are free.
+    pub fn query_cost(&self) -> u64 {
+        self.cost.load(Ordering::Relaxed)
+    }
+
+    // Pull requests in `states` for every repo in the owner's organization, or
+    // only the given repos. Returns (repo, response) pairs.
+    pub async fn collect_stats(
+        &self,
+        owner: &str,
+        repos: Option<Vec<String>>,
+        window: DateWindow,
+    ) -> Result<Fetched> {
+        let bar = self.progress_bar();
+        let (sender, receiver) = mpsc::unbounded();
+        let mut queue = RepositoryQueue::new(sender, &bar, self.repo_pattern.as_ref());
+        let discovered = repos.is_none();
+        let discover = async move {
+            match repos {
+                Some(repos) => queue.push(owner, repos),
+                None => {
+                    self.each_repository_page(owner, &mut |page| queue.push(owner, page))
+                        .await?
+                }
+            }
+            queue.finish();
+            Ok(())
+        };
+        let ((), fetched) = futures::try_join!(discover, self.fetch_all(receiver, &bar, window, false, discovered))?;
+        Ok(fetched)
+    }
+
+    // Every repo in every organization of the enterprise. Repo names are
+    // qualified with their org so contributions stay distinguishable.
+    pub async fn collect_enterprise_stats(
+        &self,
+        enterprise: &str,
+        window: DateWindow,
+    ) -> Result<Fetched> {
+        let bar = self.progress_bar();
+        let (sender, receiver) = mpsc::unbounded();
+        let mut queue = RepositoryQueue::new(sender, &bar, self.repo_pattern.as_ref());
+        let discover = async move {
+            for org in self.list_organizations(enterprise).await? {
+                tracing::info!("Discovering repos in organization: {}", org);
+                self.each_repository_page(&org, &mut |page| queue.push(&org, page))
+                    .await?;
+            }
+            queue.finish();
+            Ok(())
+        };
+        let ((), fetched) = futures::try_join!(discover, self.fetch_all(receiver, &bar, window, true, true))?;
+        Ok(fetched)
+    }
+
+    // The (owner, repo) pairs collect_stats would fetch, without fetching
+    // them. Listing an owner's repositories still takes queries.
+    pub async fn resolve_repositories(
+        &self,
+        owner: &str,
+        repos: Option<Vec<String>>,
+    ) -> Result<Vec<(String, String)>> {
+        let repos = match repos {
+            Some(repos) => repos,
+            None => self.list_repositories(owner).await?,
+        };
+        let repositories = repos.into_iter().map(|repo| (owner.to_string(), repo)).collect();
+        Ok(self.matching(repositories))
+    }
+
+    pub async fn resolve_enterprise_repositories(&self, enterprise: &str) -> Result<Vec<(String, String)>> {
+        let mut repositories = Vec::new();
+        for org in self.list_organizations(enterprise).await? {
+            tracing::info!("Discovering repos in organization: {}", org);
+            for repo in self.list_repositories(&org).await? {
+                repositories.push((org.clone(), repo));
+            }
+        }
+        Ok(self.matching(repositories))
+    }
+
+    // Estimated rate-limit cost of one page of the pull request query, by
+    // GitHub's rule: one request per connection, plus one per parent node for
+    // each nested connection, divided by 100.
+    pub fn stats_query_cost(&self) -> u64 {
+        let nested =
+            2 + self.options.dismissals as u64 + self.options.coauthors as u64 + self.options.labels as u64;
+        ((1 + nested * 100) as f64 / 100.0).round().max(1.0) as u64
+    }
+
+    // The (owner, repo) pairs whose repo name matches --repo-pattern.
+    fn matching(&self, repositories: Vec<(String, String)>) -> Vec<(String, String)> {
+        let Some(pattern) = &self.repo_pattern else {
+            return repositories;
+        };
+        let total = repositories.len();
+        let matching: Vec<_> = repositories
+            .into_iter()
+            .filter(|(_, repo)| pattern.matches(repo))
+            .collect();
+        tracing::info!("{} of {} repositories match {}", matching.len(), total, pattern);
+        matching
+    }
+
+    // Hidden unless enabled; indicatif also hides it when stderr isn't a
+    // terminal, and log lines are printed above it. Its length grows as
+    // repositories are discovered.
+    fn progress_bar(&self) -> ProgressBar {
+        let bar = if self.progress {
+            ProgressBar::new(0)
+        } else {
+            ProgressBar::hidden()
+        };
+        bar.set_style(
+            ProgressStyle::with_template("{spinner} {pos}/{len} repos {wide_msg}")
+                .expect("valid progress template"),
+        );
+        bar.enable_steady_tick(std::time::Duration::from_millis(100));
+        bar
+    }
+
+    // Fetches repositories as they come off the queue, so the first ones are
+    // being fetched while later pages of the listing are still loading.
+    // `discovered` says whether they came from listing an owner's
+    // repositories rather than from the caller.
+    async fn fetch_all(
+        &self,
+        repositories: mpsc::UnboundedReceiver<(String, String)>,
+        bar: &ProgressBar,
+        window: DateWindow,
+        qualify_repos: bool,
+        discovered: bool,
+    ) -> Result<Fetched> {
+        let mut results = repositories
+            .map(|(owner, repo)| async move {
+                if bar.is_hidden() {
+                    tracing::info!("Processing repo: {}/{}", owner, repo);
+                }
+                bar.set_message(format!("{}/{}", owner, repo));
+                let stats = self.fetch_repository(&owner, &repo, window).await;
+                let repo = if qualify_repos {
+                    format!("{}/{}", owner, repo)
+                } else {
+                    repo
+                };
+                if let (Ok(_), Some(remaining)) = (&stats, self.remaining()) {
+                    bar.suspend(|| tracing::info!("Fetched {} (rate limit remaining: {})", repo, remaining));
+                }
+                bar.inc(1);
+                (repo, stats)
+            })
+            .buffer_unordered(self.concurrency);
+        let mut fetched = Fetched {
+            repositories: BTreeMap::new(),
+            failures: Vec::new(),
+            statuses: Vec::new(),
+        };
+        while let Some((repo, stats)) = results.next().await {
+            let stats = match stats {
+                Ok(stats) => stats,
+                Err(error) => match RepositoryStatus::of_error(&error, discovered) {
+                    RepositoryStatus::AccessDenied => {
+                        bar.suspend(|| tracing::warn!("Skipping {}: {:#}", repo, error));
+                        fetched.statuses.push((repo, RepositoryStatus::AccessDenied));
+                        continue;
+                    }
+                    // Dropping the stream cancels the fetches still in flight.
+                    _ if self.fail_fast => {
+                        bar.finish_and_clear();
+                        return Err(error.context(format!("failed to fetch {}", repo)));
+                    }
+                    status => {
+                        bar.suspend(|| tracing::error!("Failed to fetch {}: {:#}", repo, error));
+                        fetched.statuses.push((repo.clone(), status));
+                        fetched.failures.push((repo, error));
+                        continue;
+                    }
+                },
+            };
+            let status = match stats.pull_requests().map_or(0, |pull_requests| pull_requests.nodes.len()) {
+                0 => RepositoryStatus::Empty,
+                count => RepositoryStatus::Fetched(count as u64),
+            };
+            fetched.statuses.push((repo.clone(), status));
+            fetched.repositories.insert(repo, stats);
+        }
+        bar.finish_and_clear();
+        fetched.failures.sort_by(|a, b| a.0.cmp(&b.0));
+        fetched.statuses.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(fetched)
+    }
+
+    // Pages back through the repo's pull requests until the date
+    // window is covered, then drops those outside it.
+    pub async fn fetch_repository(
+        &self,
+        owner: &str,
+        repo: &str,
+        window: DateWindow,
+    ) -> Result<RepositoryResponse> {
+        let mut stats = self.get_stats(owner, repo, None).await?;
+        let include_unmerged = self.states.iter().any(|state| *state != PullRequestState::Merged);
+        // Older pull requests are left out by the caller's filter anyway.
+        let floor = match (window.since, self.opened_since) {
+            (Some(since), Some(opened_since)) => Some(since.max(opened_since)),
+            (since, opened_since) => since.or(opened_since),
+        };
+        // Each page needs the cursor of the one before, so a big repository
+        // costs one round trip per 100 pull requests, one after another.
+        if stats.has_next_page(floor) {
+            let total = stats.pull_requests().map_or(0, |pull_requests| pull_requests.total_count);
+            tracing::debug!(
+                "{}/{}: {} pull requests, up to {} pages fetched one after another",
+                owner,
+                repo,
+                total,
+                total.div_ceil(100)
+            );
+        }
+        while stats.has_next_page(floor) {
+            let cursor = stats.next_cursor();
+            tracing::debug!("{}/{}: next page after cursor {}", owner, repo, cursor);
+            let next_resp = self.get_stats(owner, repo, Some(cursor)).await?;
+            stats.extend(next_resp);
+        }
+        stats.trim(window, &self.exclude_ranges, include_unmerged);
+        if let Some(pull_requests) = stats.pull_requests_mut() {
+            for pr in pull_requests.nodes.iter_mut() {
+                self.complete_discussion(owner, repo, pr).await?;
+            }
+        }
+        Ok(stats)
+    }
+
+    // The main query takes the first 100 reviews and comments of each pull
+    // request; busier ones get the rest with follow-up queries.
+    async fn complete_discussion(&self, owner: &str, repo: &str, pr: &mut PullRequest) -> Result<()> {
+        while let Some(cursor) = pr.reviews.next_cursor() {
+            tracing::debug!("{}/{}#{}: next page of reviews after cursor {}", owner, repo, pr.number, cursor);
+            let page = self.get_discussion_page(owner, repo, pr.number, "reviews", &cursor).await?;
+            let reviews = page
+                .reviews
+                .ok_or_else(|| anyhow::anyhow!("{}/{}#{}: no reviews in response", owner, repo, pr.number))?;
+            pr.reviews.extend(reviews);
+        }
+        while let Some(cursor) = pr.comments.next_cursor() {
+            tracing::debug!("{}/{}#{}: next page of comments after cursor {}", owner, repo, pr.number, cursor);
+            let page = self.get_discussion_page(owner, repo, pr.number, "comments", &cursor).await?;
+            let comments = page
+                .comments
+                .ok_or_else(|| anyhow::anyhow!("{}/{}#{}: no comments in response", owner, repo, pr.number))?;
+            pr.comments.extend(comments);
+        }
+        Ok(())
+    }
+
+    async fn make_request(&self, query: &str) -> Result<String> {
+        let cached = self
+            .cache
+            .as_ref()
+            .map(|cache| (cache, ResponseCache::key(&self.credentials, self.url.as_str(), query)));
+        if let Some(body) = cached.as_ref().and_then(|(cache, key)| cache.get(key)) {
+            tracing::debug!("GraphQL response from cache: {} bytes", body.len());
+            return Ok(body);
+        }
+        let mut attempt = 0;
+        loop {
+            let (i, token) = self.tokens.acquire(self.concurrency as u64).await?;
+            tracing::debug!("GraphQL request with token #{}: {} bytes", i + 1, query.len());
+            let response = self.post(&token, query).await;
+            let retry_reason = match response {
+                Ok(HttpResponse { status, headers, body }) => {
+                    self.tokens.update(i, &headers);
+                    if status == reqwest::StatusCode::UNAUTHORIZED {
+                        tracing::warn!("GitHub token #{} was rejected (401), no longer using it", i + 1);
+                        self.tokens.invalidate(i);
+                        continue;
+                    }
+                    let retry_after = headers
+                        .get("retry-after")
+                        .and_then(|value| value.to_str().ok()?.parse().ok());
+                    tracing::debug!("GraphQL response {}: {} bytes", status, body.len());
+                    // Waiting out a secondary rate limit doesn't use up a retry.
+                    if let Some(wait) = secondary_rate_limit_wait(status, retry_after, &body) {
+                        tracing::warn!(
+                            "Secondary rate limit hit with token #{}, sleeping {}s before resuming",
+                            i + 1,
+                            wait.as_secs()
+                        );
+                        tokio::time::sleep(wait).await;
+                        continue;
+                    }
+                    if is_retryable_status(status) {
+                        status.to_string()
+                    } else if !status.is_success() {
+                        return Err(anyhow::anyhow!("GitHub API returned {}: {}", status, body));
+                    } else if is_malformed(&body) {
+                        "malformed response body".to_string()
+                    } else {
+                        self.record_cost(&body);
+                        if let Some((cache, key)) = &cached {
+                            if let Err(e) = cache.put(key, &body) {
+                                tracing::warn!("Couldn't write to the response cache: {:#}", e);
+                            }
+                        }
+                        return Ok(body);
+                    }
+                }
+                Err(e) if e.is_retryable() => e.to_string(),
+                Err(e) => return Err(e.into()),
+            };
+            if attempt >= self.max_retries {
+                return Err(anyhow::anyhow!(
+                    "giving up after {} retries: {}",
+                    self.max_retries,
+                    retry_reason
+                ));
+            }
+            let delay = backoff(attempt);
+            attempt += 1;
+            tracing::warn!(
+                "Request failed ({}), retrying in {:.1}s ({}/{})",
+                retry_reason,
+                delay.as_secs_f64(),
+                attempt,
+                self.max_retries
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    async fn post(&self, token: &str, query: &str) -> Result<HttpResponse, TransportError> {
+        self.transport
+            .post(GraphQlRequest {
+                url: &self.url,
+                token,
+                user_agent: &self.user_agent,
+                query,
+            })
+            .await
+    }
+
+    fn record_cost(&self, body: &str) {
+        let rate_limit = serde_json::from_str::<RateLimitResponse>(body)
+            .ok()
+            .and_then(|response| response.data?.rate_limit);
+        if let Some(rate_limit) = rate_limit {
+            tracing::debug!(
+                "Query cost {} points, {} remaining until {}",
+                rate_limit.cost,
+                rate_limit.remaining,
+                rate_limit.reset_at
+            );
+            self.cost.fetch_add(rate_limit.cost, Ordering::Relaxed);
+        }
+    }
+
+    async fn get_repositories(
+        &self,
+        owner: &str,
+        owner_type: OwnerType,
+        after: Option<String>,
+    ) -> Result<OrganizationResponse> {
+        // A user's repositories default to those they collaborate on too.
+        let (field, affiliations) = match owner_type {
+            OwnerType::User => ("user", ", ownerAffiliations: [OWNER]"),
+            OwnerType::Org | OwnerType::Auto => ("organization", ""),
+        };
+        let query = format!(
+            r#"
+            query {{
+              rateLimit {{ cost remaining resetAt }}
+              {}(login: "{}") {{
+                repositories(first: 100, after: {}{}) {{
+                  edges {{
+                    node {{
+                      name
+                    }}
+                  }}
+                  pageInfo {{
+                    endCursor
+                    hasNextPage
+                  }}
+                }}
+              }}
+            }}
+            "#,
+            field,
+            owner,
+            graphql_cursor(&after),
+            affiliations
+        );
+
+        let raw_resp = self.make_request(&query).await?;
+        let response: OrganizationResponse = parse_response(&raw_resp)?;
+        if response.data.organization.is_none() {
+            return Err(ApiError::Inaccessible(field, owner.to_string()).into());
+        }
+        Ok(response)
+    }
+
+    pub async fn list_repositories(&self, owner: &str) -> Result<Vec<String>> {
+        let mut repositories = Vec::new();
+        self.each_repository_page(owner, &mut |page| repositories.extend(page))
+            .await?;
+        Ok(repositories)
+    }
+
+    // Hands each page of the owner's repositories to `page` as it arrives,
+    // or the whole list at once when the repository list cache has it.
+    async fn each_repository_page(&self, owner: &str, page: &mut impl FnMut(Vec<String>)) -> Result<()> {
+        let Some(cache) = &self.repository_lists else {
+            return self.each_listed_repository_page(owner, page).await;
+        };
+        let key = ResponseCache::key(
+            &self.credentials,
+            self.url.as_str(),
+            &format!("repositories of {} ({:?})", owner, self.owner_type),
+        );
+        if let Some(repositories) = cache.get(&key) {
+            tracing::debug!("{}: {} repositories from the repository list cache", owner, repositories.len());
+            page(repositories);
+            return Ok(());
+        }
+        let mut listed = Vec::new();
+        self.each_listed_repository_page(owner, &mut |repositories| {
+            listed.extend(repositories.iter().cloned());
+            page(repositories);
+        })
+        .await?;
+        if let Err(e) = cache.put(&key, &listed) {
+            tracing::warn!("Couldn't write to the repository list cache: {:#}", e);
+        }
+        Ok(())
+    }
+
+    async fn each_listed_repository_page(&self, owner: &str, page: &mut impl FnMut(Vec<String>)) -> Result<()> {
+        if self.owner_type != OwnerType::Auto {
+            return self.each_owned_repository_page(owner, self.owner_type, page).await;
+        }
+        // A missing organization fails on the first page, before anything
+        // was handed over, so falling back can't repeat repositories.
+        match self.each_owned_repository_page(owner, OwnerType::Org, page).await {
+            Err(e)
+                if matches!(
+                    e.downcast_ref::<ApiError>(),
+                    Some(ApiError::NotFound(_) | ApiError::Inaccessible(..))
+                ) =>
+            {
+                tracing::info!("{} is not an organization, listing the user's repositories", owner);
+                self.each_owned_repository_page(owner, OwnerType::User, page).await
+            }
+            result => result,
+        }
+    }
+
+    async fn each_owned_repository_page(
+        &self,
+        owner: &str,
+        owner_type: OwnerType,
+        page: &mut impl FnMut(Vec<String>),
+    ) -> Result<()> {
+        let mut repositories = self.get_repositories(owner, owner_type, None).await?;
+        page(repositories.repositories());
+        while repositories.has_next_page() {
+            let cursor = repositories.next_cursor();
+            tracing::debug!("{}: next page of repos after cursor {}", owner, cursor);
+            repositories = self.get_repositories(owner, owner_type, Some(cursor)).await?;
+            page(repositories.repositories());
+        }
+        Ok(())
+    }
+
+    async fn get_organizations(
+        &self,
+        enterprise: &str,
+        after: Option<String>,
+    ) -> Result<EnterpriseResponse> {
+        let query = format!(
+            r#"
+            query {{
+              rateLimit {{ cost remaining resetAt }}
+              enterprise(slug: "{}") {{
+                organizations(first: 100, after: {}) {{
+                  nodes {{
+                    login
+                  }}
+                  pageInfo {{
+                    endCursor
+                    hasNextPage
+                  }}
+                }}
+              }}
+            }}
+            "#,
+            enterprise,
+            graphql_cursor(&after)
+        );
+
+        let raw_resp = self.make_request(&query).await?;
+        parse_response(&raw_resp)
+    }
+
+    pub async fn list_organizations(&self, enterprise: &str) -> Result<Vec<String>> {
+        let mut organizations = self.get_organizations(enterprise, None).await?;
+        while organizations.has_next_page() {
+            let cursor = organizations.next_cursor();
+            tracing::debug!("{}: next page of organizations after cursor {}", enterprise, cursor);
+            let next_page = self.get_organizations(enterprise, Some(cursor)).await?;
+            organizations.extend(next_page);
+        }
+        Ok(organizations.organizations())
+    }
+
+    async fn get_team_members(&self, org: &str, team: &str, after: Option<String>) -> Result<TeamResponse> {
+        let query = format!(
+            r#"
+            query {{
+              rateLimit {{ cost remaining resetAt }}
+              organization(login: "{}") {{
+                team(slug: "{}") {{
+                  members(first: 100, after: {}) {{
+                    nodes {{
+                      login
+                    }}
+                    pageInfo {{
+                      endCursor
+                      hasNextPage
+                    }}
+                  }}
+                }}
+              }}
+            }}
+            "#,
+            org,
+            team,
+            graphql_cursor(&after)
+        );
+
+        let raw_resp = self.make_request(&query).await?;
+        let response: TeamResponse = parse_response(&raw_resp)?;
+        if response.members().is_none() {
+            return Err(ApiError::Inaccessible("team", format!("{}/{}", org, team)).into());
+        }
+        Ok(response)
+    }
+
+    // The login behind every token in the pool, in order. Sent straight to
+    // the API, bypassing the cache and the pool's 401 handling, so a bad
+    // token stops the run here instead of surfacing as empty stats later.
+    pub async fn check_auth(&self) -> Result<Vec<String>> {
+        let mut logins = Vec::new();
+        for (i, token) in self.tokens.all().iter().enumerate() {
+            let HttpResponse { status, headers, body } = self.post(token, "query { viewer { login } }").await.map_err(|e| {
+                anyhow::Error::new(e).context(format!("couldn't reach {} to check the GitHub token", self.url))
+            })?;
+            self.tokens.update(i, &headers);
+            if status == reqwest::StatusCode::UNAUTHORIZED {
+                return Err(anyhow::anyhow!(
+                    "GitHub token #{} was rejected (401 Bad credentials): check that it is correct and hasn't expired or been revoked",
+                    i + 1
+                ));
+            }
+            if !status.is_success() {
+                return Err(anyhow::anyhow!(
+                    "GitHub API returned {} while checking token #{}: {}",
+                    status,
+                    i + 1,
+                    body
+                ));
+            }
+            let response: ViewerResponse = parse_response(&body)?;
+            logins.push(response.data.viewer.login);
+        }
+        Ok(logins)
+    }
+
+    // The lowercased logins of a team's members, child teams included.
+    // Fetched once per run, before any repository.
+    pub async fn team_members(&self, org: &str, team: &str) -> Result<HashSet<String>> {
+        let mut members = HashSet::new();
+        let mut after = None;
+        loop {
+            let response = self.get_team_members(org, team, after).await?;
+            let Some(page) = response.members() else {
+                break;
+            };
+            members.extend(page.nodes.iter().map(|member| member.login.to_lowercase()));
+            if !page.page_info.has_next_page {
+                break;
+            }
+            tracing::debug!("{}/{}: next page of team members after cursor {}", org, team, page.page_info.end_cursor);
+            after = Some(page.page_info.end_cursor.clone());
+        }
+        tracing::info!("Team {}/{} has {} members", org, team, members.len());
+        Ok(members)
+    }
+
+    // Selection for a pull request's `reviews` or `comments` connection,
+    // shared by the main query and the follow-up pages.
+    fn discussion_fields(&self, connection: &str) -> String {
+        let body_text = if self.options.comment_words { "bodyText" } else { "" };
+        let review_fields = if connection == "reviews" {
+            r#"state
+                                    submittedAt
+                                    comments {
+                                        totalCount
+                                    }"#
+        } else {
+            ""
+        };
+        format!(
+            r#"totalCount
+                                nodes {{
+                                    author {{
+                                        login
+                                    }}
+                                    {}
+                                    {}
+                                }}
+                                pageInfo {{
+                                    endCursor
+                                    hasNextPage
+                                }}"#,
+            review_fields, body_text
+        )
+    }
+
+    async fn get_discussion_page(
+        &self,
+        owner: &str,
+        repo: &str,
+        number: u64,
+        connection: &str,
+        after: &str,
+    ) -> Result<PullRequestPage> {
+        let query = format!(
+            r#"
+            query {{
+                rateLimit {{ cost remaining resetAt }}
+                repository(owner: "{}", name: "{}") {{
+                    pullRequest(number: {}) {{
+                        {}(first: 100, after: {}) {{
+                            {}
+                        }}
+                    }}
+                }}
+            }}
+            "#,
+            owner,
+            repo,
+            number,
+            connection,
+            graphql_cursor(&Some(after.to_string())),
+            self.discussion_fields(connection)
+        );
+        let raw_resp = self.make_request(&query).await?;
+        let page: PullRequestPageResponse = parse_response(&raw_resp)?;
+        Ok(page.data.repository.pull_request)
+    }
+
+    async fn get_stats(
+        &self,
+        owner: &str,
+        repo: &str,
+        after: Option<String>,
+    ) -> Result<RepositoryResponse> {
+        let timeline_items = if self.options.dismissals {
+            r#"timelineItems(first: 100, itemTypes: [REVIEW_DISMISSED_EVENT]) {
+                                nodes {
+                                    ... on ReviewDismissedEvent {
+                                        review {
+                                            author {
+                                                login
+                                            }
+                                        }
+                                        pullRequestCommit {
+                                            id
+                                        }
+                                    }
+                                }
+                            }"#
+        } else {
+            ""
+        };
+        let merge_commit = if self.options.merge_methods {
+            r#"mergeCommit {
+                                messageHeadline
+                                parents {
+                                    totalCount
+                                }
+                            }"#
+        } else {
+            ""
+        };
+        let review_decision = if self.options.unblocking { "reviewDecision" } else { "" };
+        let coauthors = if self.options.coauthors {
+            r#"body
+                            commits(first: 100) {
+                                nodes {
+                                    commit {
+                                        message
+                                    }
+                                }
+                            }"#
+        } else {
+            ""
+        };
+        let labels = if self.options.labels {
+            r#"labels(first: 100) {
+                                nodes {
+                                    name
+                                }
+                            }"#
+        } else {
+            ""
+        };
+        let query = format!(
+            r#"
+            query {{
+                rateLimit {{ cost remaining resetAt }}
+                repository(owner: "{}", name: "{}") {{
+                    primaryLanguage {{
+                        name
+                    }}
+                    pullRequests(first: 100, after: {}, states: [{}]{}, orderBy: {{field: UPDATED_AT, direction: DESC}}) {{
+                        nodes {{
+                            number
+                            title
+                            url
+                            baseRefName
+                            createdAt
+                            updatedAt
+                            mergedAt
+                            mergedBy {{
+                                login
+                            }}
+                            additions
+                            deletions
+                            changedFiles
+                            author {{
+                                login
+                            }}
+                            reviews(first: 100) {{
+                                {}
+                            }}
+                            comments(first: 100) {{
+                                {}
+                            }}
+                            {}
+                            {}
+                            {}
+                            {}
+                            {}
+                        }}
+                       pageInfo {{
+                            endCursor
+                            hasNextPage
+                       }}
+                       totalCount
+                    }}
+                }}
+            }}
+            "#,
+            owner,
+            repo,
+            graphql_cursor(&after),
+            self.states
+                .iter()
+                .map(PullRequestState::as_graphql)
+                .collect::<Vec<_>>()
+                .join(", "),
+            match &self.base_branch {
+                Some(branch) => format!(", baseRefName: {}", serde_json::Value::String(branch.clone())),
+                None => String::new(),
+            },
+            self.discussion_fields("reviews"),
+            self.discussion_fields("comments"),
+            timeline_items,
+            merge_commit,
+            review_decision,
+            coauthors,
+            labels
+        );
+        let raw_resp = self.make_request(&query).await?;
+        let response: RepositoryResponse = parse_response(&raw_resp)?;
+        if response.data.repository.is_none() {
+            return Err(ApiError::Inaccessible("repository", format!("{}/{}", owner, repo)).into());
+        }
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // A merged pull request by alice as the API returns it, with the fields
+    // in `overrides` replacing the defaults.
+    fn pull_request(overrides: serde_json::Value) -> PullRequest {
+        let mut pr = json!({
+            "number": 1,
+            "title": "Change",
+            "url": "https://github.com/acme/widgets/pull/1",
+            "createdAt": "2024-01-01T09:00:00Z",
+            "updatedAt": "2024-01-02T09:00:00Z",
+            "mergedAt": "2024-01-02T09:00:00Z",
+            "additions": 10,
+            "deletions": 5,
+            "changedFiles": 1,
+            "author": { "login": "alice" },
+            "reviews": { "totalCount": 0, "nodes": [] },
+            "comments": { "totalCount": 0, "nodes": [] },
+        });
+        for (key, value) in overrides.as_object().expect("overrides are an object") {
+            pr[key] = value.clone();
+        }
+        serde_json::from_value(pr).expect("a valid pull request")
+    }
+
+    fn reviews(reviews: &[(&str, &str)]) -> serde_json::Value {
+        let nodes: Vec<_> = reviews
+            .iter()
+            .enumerate()
+            .map(|(i, (login, state))| {
+                json!({
+                    "author": { "login": login },
+                    "state": state,
+                    "submittedAt": format!("2024-01-01T1{}:00:00Z", i),
+                    "comments": { "totalCount": 0 },
+                })
+            })
+            .collect();
+        json!({ "totalCount": nodes.len(), "nodes": nodes })
+    }
+
+    fn report(users: ScoredUser) -> Report {
+        Report {
+            owner: "acme".to_string(),
+            leaderboard: Leaderboard {
+                users,
+                unattributable: None,
+            },
+            by_repo: None,
+            by_language: None,
+            summary: Summary {
+                pull_requests: 1,
+                additions: 10,
+                deletions: 5,
+                reviewers: 1,
+                avg_loc_per_pr: 15.0,
+            },
+            data_span: DateBounds::new(DateWindow::default()),
+            approval_latency: None,
+            cycle_time: None,
+            merge_methods: None,
+            reciprocity: None,
+            most_discussed: None,
+            activity: None,
+            warnings: Warnings(Vec::new()),
+        }
+    }
+
+    fn scored(prs: &[PullRequest]) -> ScoredUser {
+        let mut users = GitHubUsers(HashMap::new());
+        for pr in prs {
+            users.add_pull_request(pr, &UserFilter::default(), false);
+        }
+        users.finalize(&15.0, &HashMap::new(), &ScoringWeights::default())
+    }
+
+    fn merged_on(number: u64, date: &str) -> PullRequest {
+        let merged_at = format!("{}T12:00:00Z", date);
+        pull_request(json!({ "number": number, "mergedAt": merged_at, "updatedAt": merged_at }))
+    }
+
+    fn response(prs: Vec<PullRequest>) -> RepositoryResponse {
+        RepositoryResponse {
+            data: Data {
+                repository: Some(Repository {
+                    primary_language: None,
+                    pull_requests: PullRequests {
+                        total_count: prs.len() as u64,
+                        nodes: prs,
+                        page_info: PageInfo {
+                            end_cursor: "cursor".to_string(),
+                            has_next_page: true,
+                        },
+                    },
+                }),
+            },
+        }
+    }
+
+    fn numbers(response: &RepositoryResponse) -> Vec<u64> {
+        response.pull_requests().unwrap().nodes.iter().map(|pr| pr.number).collect()
+    }
+
+    fn date(s: &str) -> NaiveDate {
+        parse_date(s).unwrap()
+    }
+
+    #[test]
+    fn json_output_round_trips() {
+        let pr = pull_request(json!({ "reviews": reviews(&[("bob", "APPROVED")]) }));
+        let report = report(scored(&[pr]));
+        let fields = Format::Json.default_fields(false, false);
+        let rendered = render(&report, Format::Json, &fields, JsonShape::Array, false).unwrap();
+
+        let prior: PriorReport = serde_json::from_str(&rendered).unwrap();
+        assert_eq!(prior.schema_version, Some(JSON_SCHEMA_VERSION));
+        assert_eq!(prior.summary.pull_requests, 1);
+        let users = prior.users();
+        let logins: Vec<&str> = users.iter().map(|(login, _)| *login).collect();
+        assert_eq!(logins, ["alice", "bob"]);
+        assert_eq!(users[0].1.pull_requests, 1);
+        assert_eq!(users[0].1.additions, 10);
+        assert_eq!(users[1].1.approvals, 1);
+        assert_eq!(users[1].1.score, 15.0);
+    }
+
+    #[test]
+    fn cursors_are_quoted_and_escaped() {
+        assert_eq!(graphql_cursor(&Some(r#"a"b\c"#.to_string())), r#""a\"b\\c""#);
+        assert_eq!(graphql_cursor(&Some("Y3Vyc29yOjI=".to_string())), r#""Y3Vyc29yOjI=""#);
+        assert_eq!(graphql_cursor(&None), "null");
+    }
+
+    #[test]
+    fn json_shapes_render_in_rank_order() {
+        let pr = pull_request(json!({ "reviews": reviews(&[("bob", "APPROVED")]) }));
+        let report = report(scored(&[pr]));
+        let fields = [Field::User, Field::Score, Field::Approvals];
+        let render = |shape| -> serde_json::Value {
+            serde_json::from_str(&render(&report, Format::Json, &fields, shape, false).unwrap()).unwrap()
+        };
+        assert_eq!(
+            render(JsonShape::Array)["users"],
+            json!([
+                { "user": "alice", "score": 17.5, "approvals": 0 },
+                { "user": "bob", "score": 15.0, "approvals": 1 },
+            ])
+        );
+        let object = render(JsonShape::Object);
+        let users = object["users"].as_object().unwrap();
+        assert_eq!(users.keys().collect::<Vec<_>>(), ["alice", "bob"]);
+        assert_eq!(users["bob"], json!({ "score": 15.0, "approvals": 1 }));
+    }
+
+    #[test]
+    fn prior_reports_parse_in_either_shape() {
+        let summary = r#""summary": {"pull_requests": 3, "additions": 30, "deletions": 6, "reviewers": 1, "avg_loc_per_pr": 12.0}"#;
+        let array = format!(
+            r#"{{"schema_version": 1, "users": [{{"user": "alice", "pull_requests": 3}}, {{"user": "bob", "approvals": 2}}], {}}}"#,
+            summary
+        );
+        let object = format!(
+            r#"{{"schema_version": 1, "users": {{"alice": {{"pull_requests": 3}}, "bob": {{"approvals": 2}}}}, {}}}"#,
+            summary
+        );
+        for (text, array_shaped) in [(array, true), (object, false)] {
+            let prior: PriorReport = serde_json::from_str(&text).unwrap();
+            assert_eq!(matches!(prior.users, PriorUsers::Array(_)), array_shaped);
+            let users = prior.users();
+            assert_eq!(users.len(), 2);
+            assert_eq!((users[0].0, users[0].1.pull_requests), ("alice", 3));
+            assert_eq!((users[1].0, users[1].1.approvals), ("bob", 2));
+            assert_eq!(prior.summary.pull_requests, 3);
+        }
+    }
+
+    #[test]
+    fn excluded_ranges_drop_pull_requests_inside_the_window() {
+        let mut stats = response(vec![
+            merged_on(5, "2024-01-20"),
+            merged_on(4, "2024-01-12"),
+            merged_on(3, "2024-01-11"),
+            merged_on(2, "2024-01-10"),
+            merged_on(1, "2024-01-09"),
+        ]);
+        let window = DateWindow {
+            since: Some(date("2024-01-01")),
+            until: Some(date("2024-01-31")),
+        };
+        let excluded = [parse_date_range("2024-01-10..2024-01-12").unwrap()];
+        stats.trim(window, &excluded, false);
+        assert_eq!(numbers(&stats), [5, 1]);
+    }
+
+    #[test]
+    fn empty_users_score_and_render_without_panicking() {
+        let mut users = GitHubUsers(HashMap::new());
+        assert!(users.take_unattributable().is_none());
+        let balanced = ScoringWeights {
+            score_mode: ScoreMode::Balanced,
+            ..ScoringWeights::default()
+        };
+        assert!(users.finalize(&0.0, &HashMap::new(), &balanced).0.is_empty());
+        let mut scored = users.finalize(&0.0, &HashMap::new(), &ScoringWeights::default());
+        assert!(scored.0.is_empty());
+        scored.normalize();
+        let report = report(scored);
+        for format in [Format::Json, Format::Csv, Format::Table, Format::Prometheus, Format::JsonLines, Format::TerminalChart] {
+            let fields = format.default_fields(true, false);
+            render(&report, format, &fields, JsonShape::Array, true).unwrap();
+        }
+    }
+
+    // Both ends of the window are inclusive, so --since 2024-01-01 keeps
+    // everything merged on the 1st, and pagination goes on past a page that
+    // ends on it.
+    #[test]
+    fn pull_requests_on_the_boundary_dates_are_kept() {
+        let window = DateWindow {
+            since: Some(date("2024-01-01")),
+            until: Some(date("2024-01-31")),
+        };
+        let mut stats = response(vec![
+            merged_on(4, "2024-02-01"),
+            merged_on(3, "2024-01-31"),
+            merged_on(2, "2024-01-01"),
+            merged_on(1, "2023-12-31"),
+        ]);
+        stats.trim(window, &[], false);
+        assert_eq!(numbers(&stats), [3, 2]);
+
+        let ends_on_since = response(vec![merged_on(2, "2024-01-05"), merged_on(1, "2024-01-01")]);
+        assert!(ends_on_since.has_next_page(window.since));
+        let ends_before_since = response(vec![merged_on(2, "2024-01-05"), merged_on(1, "2023-12-31")]);
+        assert!(!ends_before_since.has_next_page(window.since));
+    }
+
+    #[test]
+    fn latest_review_only_keeps_a_flip_flopping_reviewers_final_state() {
+        let pr = pull_request(json!({
+            "reviews": reviews(&[("bob", "APPROVED"), ("bob", "CHANGES_REQUESTED"), ("bob", "APPROVED")]),
+        }));
+        let bob = |latest_review_only| {
+            let mut users = GitHubUsers(HashMap::new());
+            users.add_pull_request(&pr, &UserFilter::default(), latest_review_only);
+            let stats = &users.0["bob"];
+            (stats.approvals, stats.requested_changes, stats.prs_reviewed)
+        };
+        assert_eq!(bob(false), (2, 1, 1));
+        assert_eq!(bob(true), (1, 0, 1));
+    }
+
+    #[test]
+    fn equal_scores_rank_by_pull_requests_then_login() {
+        let authored = |number: u64, login: &str, additions: u64| {
+            pull_request(json!({ "number": number, "author": { "login": login }, "additions": additions, "deletions": 0 }))
+        };
+        let prs = [
+            authored(1, "erin", 10),
+            authored(2, "dave", 10),
+            authored(3, "carol", 5),
+            authored(4, "carol", 5),
+        ];
+        for _ in 0..10 {
+            let ranking = scored(&prs);
+            assert!(ranking.0.iter().all(|(_, stats)| stats.score == 10.0));
+            let logins: Vec<&str> = ranking.0.iter().map(|(login, _)| login.as_str()).collect();
+            assert_eq!(logins, ["carol", "dave", "erin"]);
+        }
+    }
+
+    #[test]
+    fn logins_differing_in_case_are_one_user() {
+        let prs = [
+            pull_request(json!({ "number": 1, "author": { "login": "Octocat" } })),
+            pull_request(json!({
+                "number": 2,
+                "author": { "login": "alice" },
+                "reviews": reviews(&[("octocat", "APPROVED")]),
+            })),
+            pull_request(json!({ "number": 3, "author": { "login": "OCTOCAT" } })),
+        ];
+        let mut users = GitHubUsers(HashMap::new());
+        for pr in prs.iter() {
+            users.add_pull_request(pr, &UserFilter::default(), false);
+        }
+        assert_eq!(users.0.len(), 2);
+        let octocat = &users.0["octocat"];
+        assert_eq!(octocat.login, "Octocat");
+        assert_eq!((octocat.pull_requests, octocat.approvals), (2, 1));
+    }
+
+    #[test]
+    fn unmerged_pull_requests_deserialize_and_stay_out_of_a_merged_window() {
+        let open = || pull_request(json!({ "number": 2, "mergedAt": null, "createdAt": "2024-01-05T09:00:00Z" }));
+        assert!(open().merged_at.is_none());
+        assert_eq!(open().window_date(false), None);
+        assert_eq!(open().window_date(true), Some(date("2024-01-05")));
+
+        let window = DateWindow {
+            since: Some(date("2024-01-01")),
+            until: None,
+        };
+        let mut merged_only = response(vec![open(), merged_on(1, "2024-01-03")]);
+        merged_only.trim(window, &[], false);
+        assert_eq!(numbers(&merged_only), [1]);
+        let mut with_unmerged = response(vec![open(), merged_on(1, "2024-01-03")]);
+        with_unmerged.trim(window, &[], true);
+        assert_eq!(numbers(&with_unmerged), [2, 1]);
+    }
+
+    // Pins the default formula: with an average of 85 lines per PR, a
+    // review point is worth 85, an added line 1 and a deleted line 8.5.
+    // Inline review comments earn nothing by default.
+    #[test]
+    fn default_weights_give_pinned_scores() {
+        let prs = [
+            pull_request(json!({
+                "number": 1,
+                "additions": 100,
+                "deletions": 20,
+                "reviews": {
+                    "totalCount": 2,
+                    "nodes": [
+                        {
+                            "author": { "login": "bob" },
+                            "state": "APPROVED",
+                            "submittedAt": "2024-01-01T10:00:00Z",
+                            "comments": { "totalCount": 2 },
+                        },
+                        {
+                            "author": { "login": "carol" },
+                            "state": "CHANGES_REQUESTED",
+                            "submittedAt": "2024-01-01T11:00:00Z",
+                            "comments": { "totalCount": 0 },
+                        },
+                    ],
+                },
+                "comments": { "totalCount": 1, "nodes": [{ "author": { "login": "carol" } }] },
+            })),
+            pull_request(json!({
+                "number": 2,
+                "author": { "login": "bob" },
+                "additions": 40,
+                "deletions": 10,
+                "reviews": reviews(&[("alice", "COMMENTED")]),
+            })),
+        ];
+        let mut users = GitHubUsers(HashMap::new());
+        for pr in prs.iter() {
+            users.add_pull_request(pr, &UserFilter::default(), false);
+        }
+        let scale = prs.iter().map(|pr| pr.additions + pr.deletions).sum::<u64>() as f64 / prs.len() as f64;
+        assert_eq!(scale, 85.0);
+        let ranking = users.finalize(&scale, &HashMap::new(), &ScoringWeights::default());
+        let scores: Vec<(&str, f64)> = ranking.0.iter().map(|(login, stats)| (login.as_str(), stats.score)).collect();
+        // alice: 100 + 20 × 8.5 authored, plus a commented review.
+        // carol: requested changes (2 × 85) and a comment (85).
+        // bob: 40 + 10 × 8.5 authored, plus an approval.
+        assert_eq!(scores, [("alice", 355.0), ("carol", 255.0), ("bob", 210.0)]);
+    }
+
+    // Folds pull requests the way main does: summary counts and the average
+    // LOC per PR only cover the ones the filter keeps.
+    fn summarize(prs: &[PullRequest], filter: &UserFilter) -> (u64, u64, ScoredUser) {
+        let mut users = GitHubUsers(HashMap::new());
+        let (mut count, mut lines) = (0u64, 0u64);
+        for pr in prs.iter().filter(|pr| !filter.excludes_pull_request(pr)) {
+            count += 1;
+            lines += pr.additions + pr.deletions;
+            users.add_pull_request(pr, filter, false);
+        }
+        let scale = lines as f64 / count as f64;
+        (count, lines, users.finalize(&scale, &HashMap::new(), &ScoringWeights::default()))
+    }
+
+    #[test]
+    fn excluded_bots_pull_requests_change_neither_summary_nor_scores() {
+        let filter = UserFilter {
+            bots: true,
+            ..UserFilter::default()
+        };
+        let human = pull_request(json!({ "reviews": reviews(&[("bob", "APPROVED")]) }));
+        let bot = pull_request(json!({
+            "number": 2,
+            "author": { "login": "dependabot[bot]" },
+            "additions": 4000,
+            "deletions": 3000,
+            "reviews": reviews(&[("bob", "APPROVED")]),
+        }));
+        let (prs, lines, without) = summarize(std::slice::from_ref(&human), &filter);
+        let (prs_with_bot, lines_with_bot, with) = summarize(&[human, bot], &filter);
+        assert_eq!((prs_with_bot, lines_with_bot), (prs, lines));
+        assert_eq!((prs, lines), (1, 15));
+        let scores = |ranking: &ScoredUser| -> Vec<(String, f64)> {
+            ranking.0.iter().map(|(login, stats)| (login.clone(), stats.score)).collect()
+        };
+        assert_eq!(scores(&with), scores(&without));
+        assert_eq!(scores(&with), [("alice".to_string(), 17.5), ("bob".to_string(), 15.0)]);
+    }
+
+    // Records what had been written at each flush.
+    struct Flushes {
+        written: Vec<u8>,
+        flushed: Vec<String>,
+    }
+
+    impl io::Write for Flushes {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.written.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.flushed.push(String::from_utf8_lossy(&self.written).into_owned());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn json_lines_are_flushed_one_user_at_a_time() {
+        let users = scored(&[pull_request(json!({ "reviews": reviews(&[("bob", "APPROVED")]) }))]);
+        let report = report(users);
+        let mut out = Flushes {
+            written: Vec::new(),
+            flushed: Vec::new(),
+        };
+        write_json_lines(&report, &DEFAULT_FIELDS, &mut out).unwrap();
+        let rendered = render(&report, Format::JsonLines, &DEFAULT_FIELDS, JsonShape::Array, true).unwrap();
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(out.flushed, [format!("{}\n", lines[0]), rendered.clone()]);
+        assert!(lines[0].contains("\"user\":\"alice\""));
+    }
+
+    #[test]
+    fn terminal_chart_shows_quiet_weeks_as_the_lowest_glyph() {
+        // Four weeks from Monday 2024-01-01: alice merges twice in the first
+        // week, once in the third, and never in the second or fourth.
+        let prs = [
+            merged_on(1, "2024-01-02"),
+            merged_on(2, "2024-01-03"),
+            merged_on(3, "2024-01-16"),
+        ];
+        let mut activity = WeeklyActivity::default();
+        for pr in prs.iter() {
+            activity.add_pull_request(pr);
+        }
+        let span = DateBounds::new(DateWindow {
+            since: Some(date("2024-01-01")),
+            until: Some(date("2024-01-28")),
+        });
+        assert_eq!(activity.sparkline("alice", &span), "█▁▅▁");
+        assert_eq!(activity.sparkline("Alice", &span), "█▁▅▁");
+        assert_eq!(activity.sparkline("bob", &span), "▁▁▁▁");
+
+        let mut report = report(scored(&prs));
+        report.data_span = span;
+        report.activity = Some(activity);
+        let fields = Format::TerminalChart.default_fields(false, false);
+        let chart = render(&report, Format::TerminalChart, &fields, JsonShape::Array, true).unwrap();
+        let lines: Vec<&str> = chart.lines().collect();
+        assert!(lines[0].ends_with("  activity"), "{}", lines[0]);
+        assert!(lines[1].starts_with("alice") && lines[1].ends_with("  █▁▅▁"), "{}", lines[1]);
+        let table = render(&report, Format::Table, &fields, JsonShape::Array, true).unwrap();
+        assert!(!table.contains("activity"));
+    }
+}
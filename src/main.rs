@@ -1,534 +1,1151 @@
 use anyhow::Result;
 use chrono::prelude::*;
-use clap::Parser;
-use reqwest::Client;
-use serde::{Deserialize, Serialize};
+use clap::{error::ErrorKind, parser::ValueSource, ArgAction, CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use git_stats::{
+    auth::AuthMode,
+    cache::{RepositoryListCache, ResponseCache},
+    *,
+};
+use serde::Serialize;
+use tracing::Level;
+use tracing_subscriber::{filter::Targets, layer::SubscriberExt, util::SubscriberInitExt};
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, HashSet},
     env,
-    fmt::{Display, Formatter},
-    sync::Arc,
+    ffi::OsString,
+    fs,
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+    time::Duration,
 };
-use tokio::task::JoinSet;
-
-struct GitHubUsers(HashMap<String, UserStats>);
-
-struct ScoredUser(Vec<(String, UserStats)>);
 
-impl GitHubUsers {
-    fn finalize(&mut self, weight: &u64) -> ScoredUser {
-        let mut v = Vec::new();
-        for (user, stats) in self.0.iter() {
-            let mut stats = stats.clone();
-            let score = (stats.approvals * weight) + (stats.comments * weight) + (stats.requested_changes * 2 * weight) + stats.additions + (stats.deletions * (weight / 10) );
-            stats.score = score;
-            v.push((user.clone(), stats.clone()));
-        }
-        v.sort_by(|a, b| {
-            b.1.score.cmp(&a.1.score)
-        });
-        ScoredUser(v)
-    }
+#[derive(Subcommand, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Command {
+    /// Rank every contributor (the default)
+    Rank,
+    /// Show one contributor's activity, broken down per repository
+    User {
+        /// GitHub login, matched regardless of case
+        login: String,
+    },
+    /// List the repositories a run would scan, after --repos and --repo-pattern, and stop
+    Repos {
+        /// How to print the names
+        #[arg(long, value_enum, default_value_t = RepoListFormat::Plain)]
+        format: RepoListFormat,
+    },
+    /// Print a shell completion script to stdout
+    #[command(hide = true)]
+    Completions {
+        #[serde(skip)]
+        shell: Shell,
+    },
 }
 
-
-impl Display for ScoredUser {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        writeln!(f, "[")?;
-        for (user, data) in self.0.iter() {
-            writeln!(
-                f,
-                "  {{
-    \"{}\":{{
-        \"Score\": {},
-        \"Approvals\": {},
-        \"Comments\": {}
-        \"Requested Changes\": {},
-        \"Pull Requests\": {},
-        \"Additions\": {},
-        \"Deletions\": {},
-        \"Changed Files\": {},
-    }}
-  }},",
-                user,
-                data.score,
-                data.approvals,
-                data.comments,
-                data.requested_changes,
-                data.pull_requests,
-                data.additions,
-                data.deletions,
-                data.changed_files
-            )?;
-        }
-        writeln!(f, "]")?;
-        Ok(())
-    }
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum RepoListFormat {
+    /// One name per line, as --repos-file reads them
+    Plain,
+    /// A JSON array of names
+    Json,
 }
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Serialize)]
 #[command(version, about, long_about)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
     #[arg(short, long)]
-    owner: String,
+    #[arg(required_unless_present_any = ["enterprise", "print_schema"])]
+    owner: Option<String>,
     #[arg(short, long)]
     #[arg(value_delimiter(','))]
+    #[arg(conflicts_with = "enterprise")]
     repos: Option<Vec<String>>,
-    #[arg(short, long)]
+    /// Read the repo names from a file, one per line, instead of --repos.
+    /// Blank lines and '#' comments are ignored
+    #[arg(long, value_name = "PATH")]
+    #[arg(conflicts_with_all = ["repos", "enterprise"])]
+    repos_file: Option<PathBuf>,
+    /// Whether --owner is an organization or a personal account
+    #[arg(long, value_enum, default_value_t = OwnerType::Auto)]
+    #[arg(conflicts_with = "enterprise")]
+    owner_type: OwnerType,
+    /// Only scan repositories whose name matches this glob, e.g. 'frontend-*'
+    #[arg(long, value_name = "GLOB")]
+    #[arg(value_parser=parse_repo_pattern)]
+    repo_pattern: Option<String>,
+    /// Match --repo-pattern case-sensitively
+    #[arg(long, requires = "repo_pattern")]
+    repo_pattern_case_sensitive: bool,
+    /// Scan every organization in a GitHub Enterprise account instead of one owner
+    #[arg(long, value_name = "SLUG", conflicts_with = "owner")]
+    enterprise: Option<String>,
+    /// Only count PRs merged (or opened, if not merged) on or after this date (YYYY-MM-DD)
+    #[arg(short = 'd', long, visible_alias = "date", value_name = "DATE")]
     #[arg(value_parser=parse_date)]
-    date: Option<NaiveDate>,
-}
-
-fn parse_date(s: &str) -> Result<NaiveDate> {
-    NaiveDate::parse_from_str(s, "%Y-%m-%d").map_err(|e| anyhow::anyhow!(e))
+    since: Option<NaiveDate>,
+    /// Only count PRs merged (or opened, if not merged) on or before this date (YYYY-MM-DD)
+    #[arg(long, value_name = "DATE")]
+    #[arg(value_parser=parse_date)]
+    until: Option<NaiveDate>,
+    /// Drop PRs merged within an inclusive date range, e.g. 2024-12-20..2025-01-03 (repeatable)
+    #[arg(long, value_name = "START..END")]
+    #[arg(value_parser=parse_date_range)]
+    exclude_range: Vec<DateRange>,
+    /// Only count PRs opened in the last DAYS days, and stop paginating past them
+    #[arg(long, value_name = "DAYS")]
+    max_pr_age_days: Option<u64>,
+    /// Pull request states to count, e.g. MERGED,OPEN
+    #[arg(long, value_enum, value_delimiter(','), ignore_case = true, default_value = "MERGED")]
+    states: Vec<PullRequestState>,
+    /// Only count PRs opened against this base branch, e.g. main
+    #[arg(long, value_name = "BRANCH")]
+    base_branch: Option<String>,
+    /// Only count PRs carrying this label, matched regardless of case (repeatable; any one matches)
+    #[arg(long, value_name = "NAME")]
+    label: Vec<String>,
+    /// Points per approval, times the average LOC per PR
+    #[arg(long, env = "GIT_STATS_APPROVAL_WEIGHT", default_value_t = DEFAULT_APPROVAL_WEIGHT)]
+    #[arg(value_parser=parse_weight)]
+    weight_approval: f64,
+    /// Points per comment or commented review, times the average LOC per PR
+    #[arg(long, env = "GIT_STATS_COMMENT_WEIGHT", default_value_t = DEFAULT_COMMENT_WEIGHT)]
+    #[arg(value_parser=parse_weight)]
+    weight_comment: f64,
+    /// Points per inline review comment, times the average LOC per PR
+    #[arg(long, env = "GIT_STATS_REVIEW_COMMENT_WEIGHT", default_value_t = DEFAULT_REVIEW_COMMENT_WEIGHT)]
+    #[arg(value_parser=parse_weight)]
+    weight_review_comment: f64,
+    /// Points per requested-changes review, times the average LOC per PR
+    #[arg(long, env = "GIT_STATS_REQUESTED_CHANGES_WEIGHT", default_value_t = DEFAULT_REQUESTED_CHANGES_WEIGHT)]
+    #[arg(value_parser=parse_weight)]
+    weight_requested_changes: f64,
+    /// Points per line added
+    #[arg(long, env = "GIT_STATS_ADDITION_WEIGHT", default_value_t = DEFAULT_ADDITION_WEIGHT)]
+    #[arg(value_parser=parse_weight)]
+    weight_addition: f64,
+    /// Points per line deleted, times the average LOC per PR
+    #[arg(long, env = "GIT_STATS_DELETION_WEIGHT", default_value_t = DEFAULT_DELETION_WEIGHT)]
+    #[arg(value_parser=parse_weight)]
+    weight_deletion: f64,
+    /// How a pull request's size turns into points
+    #[arg(long, value_enum, default_value_t = SizeScoring::Linear)]
+    size_scoring: SizeScoring,
+    /// Scale each review's points by the size of the pull request reviewed
+    #[arg(long)]
+    size_weighted_reviews: bool,
+    /// Halve what a pull request and its reviews score every this many days since it was merged
+    #[arg(long, value_name = "DAYS")]
+    #[arg(value_parser=parse_half_life)]
+    decay_half_life: Option<f64>,
+    /// How the points combine into a score
+    #[arg(long, value_enum, default_value_t = ScoreMode::Additive)]
+    score_mode: ScoreMode,
+    /// Multiply a reviewer's review credit by a factor, e.g. alice=1.5 (repeatable, or comma-separated)
+    #[arg(long, value_name = "LOGIN=FACTOR", env = "GIT_STATS_REVIEWER_WEIGHTS", value_delimiter(','))]
+    #[arg(value_parser=parse_reviewer_weight)]
+    reviewer_weight: Vec<(String, f64)>,
+    /// Fetch comment bodies and count the words each user wrote in reviews and comments
+    #[arg(long)]
+    comment_words: bool,
+    /// Points per comment word written (requires --comment-words)
+    #[arg(long, env = "GIT_STATS_COMMENT_WORD_WEIGHT", default_value_t = 0.0, requires = "comment_words")]
+    #[arg(value_parser=parse_weight)]
+    comment_word_weight: f64,
+    /// Also report each score rescaled to 0-100 relative to the top scorer
+    #[arg(long)]
+    normalize_score: bool,
+    /// Show the points each kind of contribution adds to the score
+    #[arg(long)]
+    explain: bool,
+    /// Output format
+    #[arg(short, long, value_enum, default_value_t = Format::Table)]
+    format: Format,
+    /// Comma-separated fields to show, in order (e.g. user,score,prs,approvals)
+    #[arg(long, value_delimiter(','))]
+    fields: Option<Vec<Field>>,
+    /// Print plain numbers in table output, without thousands separators
+    #[arg(long)]
+    no_group_digits: bool,
+    /// Give extra credit to the approval that unblocked each merge
+    #[arg(long)]
+    credit_unblocking: bool,
+    /// Extra approvals' worth of credit per unblocking approval (requires --credit-unblocking)
+    #[arg(long, env = "GIT_STATS_UNBLOCKING_WEIGHT", default_value_t = DEFAULT_UNBLOCKING_WEIGHT)]
+    #[arg(value_parser=parse_weight, requires = "credit_unblocking")]
+    unblocking_weight: f64,
+    /// Share authorship credit with co-authors named in Co-authored-by trailers
+    #[arg(long)]
+    credit_coauthors: bool,
+    /// Layout of the leaderboard in --format json
+    #[arg(long, value_enum, default_value_t = JsonShape::Array)]
+    json_shape: JsonShape,
+    /// Report the N pull requests with the most discussion
+    #[arg(long, value_name = "N")]
+    most_discussed: Option<usize>,
+    /// Count only each reviewer's final review state per pull request
+    #[arg(long)]
+    latest_review_only: bool,
+    /// Show only the N highest-ranked users
+    #[arg(long, value_name = "N")]
+    top: Option<usize>,
+    /// Leave out users who authored fewer than N pull requests, unless they reviewed or commented
+    #[arg(long, value_name = "N")]
+    min_prs: Option<u64>,
+    /// Leave out accounts whose login ends with [bot]
+    #[arg(long)]
+    exclude_bots: bool,
+    /// Leave out an account by login (repeatable); the unknown label matches deleted accounts
+    #[arg(long, value_name = "LOGIN")]
+    exclude_user: Vec<String>,
+    /// Only count members of this team in the --owner organization
+    #[arg(long, value_name = "SLUG", conflicts_with = "enterprise")]
+    team: Option<String>,
+    /// With --team, also count non-members' reviews on pull requests members authored
+    #[arg(long, requires = "team")]
+    include_outside_reviewers: bool,
+    /// Also rank contributors within each repository
+    #[arg(long)]
+    per_repo: bool,
+    /// Also break the ranking down by each repository's primary language
+    #[arg(long)]
+    by_language: bool,
+    /// Count how often each reviewer's reviews were dismissed by later pushes
+    #[arg(long)]
+    report_dismissals: bool,
+    /// Report time from opening a PR to its first approval, per author and per reviewer
+    #[arg(long)]
+    approval_latency: bool,
+    /// Report average time from opening a PR to its first review and to its merge, per author and per repository
+    #[arg(long)]
+    cycle_time: bool,
+    /// Report how pull requests were merged (merge commit, squash or rebase)
+    #[arg(long)]
+    merge_methods: bool,
+    /// Report the N most one-sided reviewer/author pairs
+    #[arg(long, value_name = "N")]
+    reciprocity: Option<usize>,
+    /// Add the counts of a previous --format json report to this run before scoring
+    #[arg(long, value_name = "PATH")]
+    merge_from: Option<PathBuf>,
+    /// Write the output to a file instead of stdout (- for stdout); a .sqlite or .db path also exports users and pull requests to SQLite
+    #[arg(long, value_name = "PATH")]
+    output: Option<PathBuf>,
+    /// With --per-repo and --format csv, write <DIR>/<repo>.csv for each repository and <DIR>/_combined.csv for the overall leaderboard
+    #[arg(long, value_name = "DIR", requires = "per_repo", conflicts_with = "output")]
+    output_dir: Option<PathBuf>,
+    /// GraphQL endpoint, e.g. https://github.example.com/api/graphql for GitHub Enterprise Server
+    #[arg(long, env = "GITHUB_API_URL", default_value = GITHUB_GRAPHQL_URL)]
+    #[arg(value_parser=parse_api_url)]
+    api_url: String,
+    /// User-Agent header sent with every request
+    #[arg(long, env = "GIT_STATS_USER_AGENT", default_value = DEFAULT_USER_AGENT)]
+    user_agent: String,
+    /// Retries on connection errors, 500/502/503/504 responses and truncated bodies, with exponential backoff
+    #[arg(long, default_value_t = 3)]
+    max_retries: u32,
+    /// Repositories fetched in parallel
+    #[arg(long, default_value_t = 5)]
+    #[arg(value_parser=parse_concurrency)]
+    concurrency: usize,
+    /// Don't show a progress bar while fetching repositories
+    #[arg(long)]
+    no_progress: bool,
+    /// Stop at the first repository that fails to fetch instead of reporting the rest
+    #[arg(long)]
+    fail_fast: bool,
+    /// Cache API responses on disk and reuse them for this many seconds
+    #[arg(long, env = "GIT_STATS_CACHE_TTL", value_name = "SECONDS")]
+    cache_ttl: Option<u64>,
+    /// Where to keep cached responses; defaults to ~/.cache/git-stats
+    #[arg(long, env = "GIT_STATS_CACHE_DIR", value_name = "PATH")]
+    cache_dir: Option<PathBuf>,
+    /// Keep each owner's repository list on disk and reuse it for this many seconds
+    #[arg(long, env = "GIT_STATS_REPO_LIST_TTL", value_name = "SECONDS")]
+    repo_list_ttl: Option<u64>,
+    /// Ignore the response and repository list caches even when their TTLs are set
+    #[arg(long)]
+    no_cache: bool,
+    /// Log more to stderr: -v for requests and pagination, -vv for HTTP internals too
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Only log warnings and errors to stderr, with no progress bar
+    #[arg(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+    /// How to authenticate with GitHub
+    #[arg(long, value_enum, default_value_t = AuthMode::Token)]
+    auth_mode: AuthMode,
+    /// GitHub App ID (requires --auth-mode app)
+    #[arg(long, env = "GITHUB_APP_ID", required_if_eq("auth_mode", "app"))]
+    app_id: Option<String>,
+    /// Installation of the GitHub App to mint a token for (requires --auth-mode app)
+    #[arg(long, env = "GITHUB_APP_INSTALLATION_ID", required_if_eq("auth_mode", "app"))]
+    installation_id: Option<u64>,
+    /// PEM private key of the GitHub App (requires --auth-mode app)
+    #[arg(long, env = "GITHUB_APP_PRIVATE_KEY_PATH", required_if_eq("auth_mode", "app"))]
+    private_key_path: Option<PathBuf>,
+    /// GitHub token to pool for rate limit (repeatable); defaults to GITHUB_TOKEN
+    #[arg(long, env = "GITHUB_TOKENS", hide_env_values = true)]
+    #[arg(value_delimiter(','))]
+    #[serde(skip)]
+    token: Vec<String>,
+    /// Give up on a request after this many seconds; it is then retried like a connection error
+    #[arg(long, default_value_t = 30, value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..))]
+    timeout: u64,
+    /// PEM file of extra root certificates to trust, e.g. an Enterprise Server's internal CA
+    #[arg(long, env = "GIT_STATS_CA_CERT", value_name = "PATH")]
+    ca_cert: Option<PathBuf>,
+    /// Don't verify the API's TLS certificate; only for testing against a self-signed server
+    #[arg(long, conflicts_with = "ca_cert")]
+    insecure: bool,
+    /// Don't check the tokens with a `viewer` query before fetching, e.g. against an offline stub
+    #[arg(long)]
+    no_auth_check: bool,
+    /// Label for contributions by deleted accounts
+    #[arg(long, default_value = DELETED_LOGIN)]
+    unknown_label: String,
+    /// Replace every login in the output with a pseudonym, user-<hash>, stable across runs (requires --anonymize-key)
+    #[arg(long)]
+    anonymize: bool,
+    /// Secret mixed into the pseudonyms so they can't be recomputed from a list of logins
+    #[arg(long, env = "GIT_STATS_ANONYMIZE_KEY", hide_env_values = true, value_name = "KEY")]
+    #[serde(skip)]
+    anonymize_key: Option<String>,
+    /// Write each real login and its pseudonym to this CSV file (requires --anonymize)
+    #[arg(long, value_name = "PATH", requires = "anonymize")]
+    anonymize_map: Option<PathBuf>,
+    /// Read settings from a TOML file; defaults to ./git-stats.toml if it exists
+    #[arg(long, value_name = "PATH")]
+    #[serde(skip)]
+    config: Option<PathBuf>,
+    /// Print the resolved configuration as JSON and exit
+    #[arg(long)]
+    #[serde(skip)]
+    print_config: bool,
+    /// Print the JSON Schema of the --format json document and exit
+    #[arg(long, hide = true)]
+    #[serde(skip)]
+    print_schema: bool,
+    /// List the repositories and estimate the queries a run would take, without fetching stats
+    #[arg(long)]
+    dry_run: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct OrganizationResponse {
-    data: OrgData,
+// The earliest opening date --max-pr-age-days lets through, counted back
+// from today.
+fn opened_since(max_pr_age_days: Option<u64>) -> Option<NaiveDate> {
+    max_pr_age_days.and_then(|days| Utc::now().date_naive().checked_sub_days(chrono::Days::new(days)))
 }
 
-impl OrganizationResponse {
-    fn has_next_page(&self) -> bool {
-        self.data.organization.repositories.page_info.has_next_page
-    }
-
-    fn next_cursor(&self) -> String {
-        self.data
-            .organization
-            .repositories
-            .page_info
-            .end_cursor
-            .clone()
-    }
-
-    fn repositories(&self) -> Vec<String> {
-        self.data
-            .organization
-            .repositories
-            .edges
-            .iter()
-            .map(|edge| edge.node.name.clone())
-            .collect()
-    }
-
-    fn extend(&mut self, other: OrganizationResponse) {
-        self.data
-            .organization
-            .repositories
-            .edges
-            .extend(other.data.organization.repositories.edges);
-        self.data.organization.repositories.page_info =
-            other.data.organization.repositories.page_info;
+fn parse_reviewer_weight(s: &str) -> Result<(String, f64)> {
+    let (login, factor) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow::anyhow!("expected <login>=<factor>, got '{}'", s))?;
+    let factor: f64 = factor.parse()?;
+    if !factor.is_finite() || factor < 0.0 {
+        return Err(anyhow::anyhow!("factor must be a non-negative number, got {}", factor));
     }
+    Ok((login.to_string(), factor))
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct OrgData {
-    organization: Organization,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct Organization {
-    repositories: Repositories,
+fn parse_weight(s: &str) -> Result<f64> {
+    let weight: f64 = s.parse()?;
+    if !weight.is_finite() || weight < 0.0 {
+        return Err(anyhow::anyhow!("weight must be a non-negative number, got {}", weight));
+    }
+    Ok(weight)
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Repositories {
-    edges: Vec<RepositoryEdge>,
-    #[serde(rename = "pageInfo")]
-    page_info: PageInfo,
+fn parse_half_life(s: &str) -> Result<f64> {
+    let days: f64 = s.parse()?;
+    if !days.is_finite() || days <= 0.0 {
+        return Err(anyhow::anyhow!("half-life must be a positive number of days, got {}", days));
+    }
+    Ok(days)
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct RepositoryEdge {
-    node: RepositoryNode,
+fn parse_concurrency(s: &str) -> Result<usize> {
+    let concurrency: usize = s.parse()?;
+    if concurrency == 0 {
+        return Err(anyhow::anyhow!("concurrency must be at least 1"));
+    }
+    Ok(concurrency)
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct RepositoryNode {
-    name: String,
+fn parse_repo_pattern(s: &str) -> Result<String> {
+    RepoPattern::new(s, true)?;
+    Ok(s.to_string())
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct RepositoryResponse {
-    data: Data,
+fn parse_api_url(s: &str) -> Result<String> {
+    graphql_url(s)?;
+    Ok(s.to_string())
 }
 
-impl RepositoryResponse {
-    fn empty() -> RepositoryResponse {
-        RepositoryResponse {
-            data: Data {
-                repository: Repository {
-                    pull_requests: PullRequests {
-                        nodes: vec![],
-                        page_info: PageInfo {
-                            end_cursor: "".to_string(),
-                            has_next_page: false,
-                        },
-                    },
-                },
-            },
-        }
-    }
+// Exit status when the report was produced but some repositories failed to
+// fetch, so CI can tell a partial run from a hard error (1) or bad usage (2).
+const PARTIAL_FETCH_EXIT_CODE: i32 = 3;
 
-    fn has_next_page(&self, max_date: Option<NaiveDate>) -> bool {
-        let in_window = if let Some(max_date) = max_date {
-            match self.data.repository.pull_requests.nodes.last() {
-                Some(last) => last.merged_at.date_naive() > max_date,
-                None => true,
-            }
-        } else {
-            true
-        };
-        in_window && self.data.repository.pull_requests.page_info.has_next_page
+fn exit_if_failed(failures: &[(String, anyhow::Error)]) {
+    if failures.is_empty() {
+        return;
     }
-
-    fn next_cursor(&self) -> String {
-        self.data
-            .repository
-            .pull_requests
-            .page_info
-            .end_cursor
-            .clone()
+    tracing::error!("{} repositories could not be fully fetched:", failures.len());
+    for (repo, error) in failures {
+        tracing::error!("  {}: {:#}", repo, error);
     }
+    let _ = io::stdout().flush();
+    std::process::exit(PARTIAL_FETCH_EXIT_CODE);
+}
 
-    fn extend(&mut self, other: RepositoryResponse) {
-        self.data
-            .repository
-            .pull_requests
-            .nodes
-            .extend(other.data.repository.pull_requests.nodes);
-        self.data.repository.pull_requests.page_info =
-            other.data.repository.pull_requests.page_info;
+// What --dry-run prints instead of a report. Only the first page of each
+// repository is certain; the number of pages depends on how many pull
+// requests were merged in the window.
+fn print_plan(fetcher: &Fetcher, repositories: &[(String, String)], window: DateWindow) {
+    let since = window.since.map_or("the first pull request".to_string(), |date| date.to_string());
+    let until = window.until.map_or("today".to_string(), |date| date.to_string());
+    let calls = repositories.len() as u64;
+    let cost = fetcher.stats_query_cost();
+    println!("Repositories to scan: {}", repositories.len());
+    for (owner, repo) in repositories {
+        println!("  {}/{}", owner, repo);
     }
-
-    fn trim(&mut self, max_date: Option<NaiveDate>) {
-        if let Some(max_date) = max_date {
-            self.data
-                .repository
-                .pull_requests
-                .nodes
-                .retain(|pr| pr.merged_at.date_naive() > max_date);
-        }
+    println!("Date window: {} to {}", since, until);
+    println!(
+        "GraphQL calls: at least {}, one per 100 merged pull requests in each repository",
+        calls
+    );
+    println!(
+        "Estimated rate-limit cost: at least {} points ({} per call)",
+        calls * cost,
+        cost
+    );
+    match fetcher.remaining() {
+        Some(remaining) => println!(
+            "Listing repositories cost {} points, {} remaining",
+            fetcher.query_cost(),
+            remaining
+        ),
+        None => println!("Listing repositories cost {} points", fetcher.query_cost()),
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Data {
-    repository: Repository,
-}
+// Read from the working directory when --config isn't given.
+const DEFAULT_CONFIG: &str = "git-stats.toml";
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Repository {
-    #[serde(rename = "pullRequests")]
-    pull_requests: PullRequests,
-}
+// Keys a config file may not set: tokens and the anonymize key belong in the
+// environment, and the others only make sense on the command line.
+const NOT_CONFIGURABLE: &[&str] = &["token", "anonymize_key", "config", "print_config", "print_schema"];
 
-#[derive(Debug, Serialize, Deserialize)]
-struct PullRequests {
-    nodes: Vec<PullRequest>,
-    #[serde(rename = "pageInfo")]
-    page_info: PageInfo,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct PageInfo {
-    #[serde(rename = "endCursor")]
-    #[serde(deserialize_with = "default_on_null")]
-    end_cursor: String,
-    #[serde(rename = "hasNextPage")]
-    has_next_page: bool,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct PullRequest {
-    reviews: Reviews,
-    comments: Comments,
-    #[serde(rename = "mergedAt")]
-    merged_at: DateTime<Utc>,
-    additions: u64,
-    deletions: u64,
-    #[serde(rename = "changedFiles")]
-    changed_files: u64,
-    #[serde(deserialize_with = "default_on_null")]
-    author: User,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct Reviews {
-    nodes: Vec<Review>,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct Review {
-    #[serde(deserialize_with = "default_on_null")]
-    author: User,
-    state: String,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct Comments {
-    nodes: Vec<Comment>,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct Comment {
-    #[serde(deserialize_with = "default_on_null")]
-    author: User,
+// Repo names from --repos-file: one per line, with blank lines and
+// everything after a '#' dropped.
+fn read_repos_file(path: &Path) -> Result<Vec<String>> {
+    let text = fs::read_to_string(path).map_err(|e| anyhow::anyhow!("can't read {}: {}", path.display(), e))?;
+    let repos: Vec<String> = text
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect();
+    if repos.is_empty() {
+        return Err(anyhow::anyhow!("{} doesn't list any repositories", path.display()));
+    }
+    Ok(repos)
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct User {
-    login: String,
+// The config file, or None if there is no --config and no git-stats.toml.
+fn read_config(path: Option<&Path>) -> Result<Option<toml::Table>> {
+    let (path, required) = match path {
+        Some(path) => (path, true),
+        None => (Path::new(DEFAULT_CONFIG), false),
+    };
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if !required && e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(anyhow::anyhow!("can't read {}: {}", path.display(), e)),
+    };
+    let config = toml::from_str(&text).map_err(|e| anyhow::anyhow!("invalid {}: {}", path.display(), e))?;
+    Ok(Some(config))
 }
 
-impl Default for User {
-    fn default() -> User {
-        User {
-            login: "Unknown".to_string(),
+fn config_value(key: &str, value: &toml::Value) -> Result<String> {
+    match value {
+        toml::Value::String(s) => Ok(s.clone()),
+        toml::Value::Integer(n) => Ok(n.to_string()),
+        toml::Value::Float(n) => Ok(n.to_string()),
+        toml::Value::Boolean(b) => Ok(b.to_string()),
+        toml::Value::Datetime(date) => Ok(date.to_string()),
+        toml::Value::Array(_) | toml::Value::Table(_) => {
+            Err(anyhow::anyhow!("'{}' has a nested array or table", key))
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize)]
-struct UserStats {
-    approvals: u64,
-    requested_changes: u64,
-    comments: u64,
-    pull_requests: u64,
-    additions: u64,
-    deletions: u64,
-    changed_files: u64,
-    score: u64,
-}
-
-impl UserStats {
-    fn new() -> UserStats {
-        UserStats {
-            approvals: 0,
-            requested_changes: 0,
-            comments: 0,
-            pull_requests: 0,
-            additions: 0,
-            deletions: 0,
-            changed_files: 0,
-            score: 0,
+// Turns the file's settings into the flags they stand for, so they go
+// through the same parsing and validation as the command line. Keys are
+// the argument names, as --print-config shows them. Settings already given
+// as a flag or an environment variable are skipped, since those win.
+fn config_flags(command: &clap::Command, config: &toml::Table, given: impl Fn(&str) -> bool) -> Result<Vec<OsString>> {
+    let mut flags = Vec::new();
+    for (key, value) in config {
+        if NOT_CONFIGURABLE.contains(&key.as_str()) {
+            return Err(anyhow::anyhow!("'{}' can't be set in a config file", key));
+        }
+        let arg = command
+            .get_arguments()
+            .find(|arg| arg.get_id() == key.as_str())
+            .ok_or_else(|| anyhow::anyhow!("unknown setting '{}'", key))?;
+        if given(key) {
+            continue;
+        }
+        let long = arg.get_long().expect("every argument has a long flag");
+        let values = match value {
+            toml::Value::Array(values) => values.iter().collect(),
+            value => vec![value],
+        };
+        for value in values {
+            match (arg.get_action(), value) {
+                (ArgAction::SetTrue, toml::Value::Boolean(set)) => {
+                    if *set {
+                        flags.push(format!("--{}", long));
+                    }
+                }
+                (ArgAction::Count, toml::Value::Integer(count)) => {
+                    flags.extend((0..*count).map(|_| format!("--{}", long)));
+                }
+                // [reviewer_weight] tables hold LOGIN = FACTOR pairs.
+                (_, toml::Value::Table(pairs)) => {
+                    for (name, value) in pairs {
+                        flags.push(format!("--{}={}={}", long, name, config_value(key, value)?));
+                    }
+                }
+                (_, value) => flags.push(format!("--{}={}", long, config_value(key, value)?)),
+            }
         }
     }
+    Ok(flags.into_iter().map(OsString::from).collect())
 }
 
-fn default_on_null<'de, T, D>(deserializer: D) -> Result<T, D::Error>
-where
-    T: Deserialize<'de> + Default,
-    D: serde::Deserializer<'de>,
-{
-    Deserialize::deserialize(deserializer).map(|x: Option<T>| x.unwrap_or_default())
-}
-
-async fn make_request(client: &Client, token: &str, query: &str) -> Result<String> {
-    client
-        .post("https://api.github.com/graphql")
-        .header("Authorization", format!("Bearer {}", token))
-        .header("User-Agent", "rust-github-stats")
-        .json(&serde_json::json!({ "query": query }))
-        .send()
-        .await?
-        .text()
-        .await
-        .map_err(|e| anyhow::anyhow!(e))
-}
-
-async fn get_repositories(
-    client: &Client,
-    token: &str,
-    owner: &str,
-    after: &str,
-) -> Result<OrganizationResponse> {
-    let query = format!(
-        r#"
-        query {{
-          organization(login: "{}") {{
-            repositories(first: 100, after: {}) {{
-              edges {{
-                node {{
-                  name
-                }}
-              }}
-              pageInfo {{
-                endCursor
-                hasNextPage
-              }}
-            }}
-          }}
-        }}
-        "#,
-        owner, after
-    );
-
-    let raw_resp = make_request(client, token, &query).await?;
-    serde_json::from_str(&raw_resp).map_err(|e| anyhow::anyhow!(e))
+// Precedence, highest first: command line, environment, config file,
+// defaults. A lenient first pass finds the config file and what the command
+// line and environment already set; the second pass parses everything
+// together and reports any errors.
+fn parse_args() -> Args {
+    let argv: Vec<OsString> = env::args_os().collect();
+    let mut command = Args::command();
+    let Ok(matches) = command.clone().ignore_errors(true).try_get_matches_from(&argv) else {
+        return Args::parse();
+    };
+    // Completions need none of the required flags, so they are written
+    // before the full parse can insist on --owner.
+    if let Some(("completions", sub)) = matches.subcommand() {
+        if let Some(shell) = sub.get_one::<Shell>("shell") {
+            let name = command.get_name().to_string();
+            clap_complete::generate(*shell, &mut command, name, &mut io::stdout());
+            std::process::exit(0);
+        }
+    }
+    let given = |id: &str| {
+        matches!(
+            matches.value_source(id),
+            Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+        )
+    };
+    let flags = read_config(matches.get_one::<PathBuf>("config").map(PathBuf::as_path)).and_then(|config| {
+        config.map_or(Ok(Vec::new()), |config| config_flags(&command, &config, given))
+    });
+    let flags = match flags {
+        Ok(flags) => flags,
+        Err(e) => command.error(ErrorKind::InvalidValue, format!("{:#}", e)).exit(),
+    };
+    let (program, rest) = argv.split_first().expect("argv holds the program name");
+    Args::parse_from(std::iter::once(program).chain(&flags).chain(rest))
 }
 
-async fn get_stats(
-    client: &Client,
-    token: &str,
-    owner: &str,
-    repo: &str,
-    after: &str,
-) -> Result<RepositoryResponse> {
-    let query = format!(
-        r#"
-        query {{
-            repository(owner: "{}", name: "{}") {{
-                pullRequests(first: 100, after: {}, states: MERGED, orderBy: {{field: CREATED_AT, direction: DESC}}) {{
-                    nodes {{
-                        mergedAt
-                        additions
-                        deletions
-                        changedFiles
-                        author {{
-                            login
-                        }}
-                        reviews(first: 100) {{
-                            nodes {{
-                                author {{
-                                    login
-                                }}
-                                state
-                            }}
-                        }}
-                        comments(first: 100) {{
-                            nodes {{
-                                author {{
-                                    login
-                                }}
-                            }}
-                        }}
-                    }}
-                   pageInfo {{
-                        endCursor
-                        hasNextPage
-                   }}
-                }}
-            }}
-        }}
-        "#,
-        owner, repo, after
-    );
-    let raw_resp = make_request(client, token, &query).await?;
-    match serde_json::from_str(&raw_resp).map_err(|e| anyhow::anyhow!(e)) {
-        Ok(resp) => Ok(resp),
-        Err(e) => {
-            println!("Error: {}", e);
-            println!("Bad Response: {}", raw_resp);
-            Ok(RepositoryResponse::empty())
-        }
+// Diagnostics go to stderr so stdout only carries the rendered report.
+// -v shows this crate's debug detail, -vv also that of its dependencies,
+// and -q only what went wrong.
+fn init_logging(verbose: u8, quiet: bool) {
+    let level = match (verbose, quiet) {
+        (_, true) => Level::WARN,
+        (0, false) => Level::INFO,
+        _ => Level::DEBUG,
+    };
+    let mut filter = Targets::new().with_target("git_stats", level);
+    if verbose >= 2 {
+        filter = filter.with_default(Level::DEBUG);
     }
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_writer(io::stderr)
+                .with_ansi(io::stderr().is_terminal())
+                .without_time()
+                .with_target(false),
+        )
+        .with(filter)
+        .init();
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let github_token = env::var("GITHUB_TOKEN").expect("GITHUB_TOKEN not set");
-    let args: Args = Args::parse();
+    let args: Args = parse_args();
+    init_logging(args.verbose, args.quiet);
+    if args.print_schema {
+        println!("{}", serde_json::to_string_pretty(&json_schema())?);
+        return Ok(());
+    }
+    if args.print_config {
+        println!("{}", serde_json::to_string_pretty(&args)?);
+        return Ok(());
+    }
+    // A SQLite export still prints the report; any other path takes its place.
+    let (sqlite_output, output) = match args.output.clone() {
+        Some(path) if is_sqlite_path(&path) => (Some(path), None),
+        Some(path) if path.as_os_str() == "-" => (None, None),
+        path => (None, path),
+    };
+    let user = match &args.command {
+        Some(Command::User { login }) => Some(login.clone()),
+        Some(Command::Rank | Command::Repos { .. }) | None => None,
+        Some(Command::Completions { .. }) => unreachable!("parse_args prints completions and exits"),
+    };
+    // The user subcommand's breakdown is the per-repo ranking, cut down.
+    let per_repo = args.per_repo || user.is_some();
+    let side_reports = args.approval_latency
+        || args.cycle_time
+        || args.merge_methods
+        || args.reciprocity.is_some()
+        || args.most_discussed.is_some();
+    if args.format == Format::Csv && user.is_some() {
+        return Err("--format csv can't hold the per-repository breakdown of the user subcommand; use json or table".into());
+    }
+    if args.output_dir.is_some() && args.format != Format::Csv {
+        return Err("--output-dir writes CSV files; use it with --format csv".into());
+    }
+    // With --output-dir, each repository's leaderboard gets a file of its own.
+    if args.format == Format::Csv && ((per_repo && args.output_dir.is_none()) || args.by_language || side_reports) {
+        return Err(
+            "--format csv only holds the leaderboard; use json or table for the other reports, or --output-dir with --per-repo"
+                .into(),
+        );
+    }
+    if args.format == Format::JsonLines && (user.is_some() || per_repo || args.by_language || side_reports) {
+        return Err("--format json-lines only holds the leaderboard; use json or table for the other reports".into());
+    }
+    // Pseudonyms are a keyed hash of the login: the same key gives the same
+    // pseudonyms every run, and without one a list of logins would undo them.
+    let anonymize_key = args.anonymize_key.clone().filter(|key| !key.is_empty());
+    if args.anonymize && anonymize_key.is_none() {
+        return Err("--anonymize needs a secret key: set GIT_STATS_ANONYMIZE_KEY or pass --anonymize-key".into());
+    }
+    let breakdown = args.explain || args.fields.as_ref().is_some_and(|fields| fields.contains(&Field::Breakdown));
+    // Balanced scores are z-scores, not sums of points, and can be negative.
+    if args.score_mode == ScoreMode::Balanced && (breakdown || args.normalize_score) {
+        return Err("--score-mode balanced can't be combined with --explain, --fields breakdown or --normalize-score".into());
+    }
+    if args.format == Format::Prometheus && breakdown {
+        return Err("--format prometheus has no form for the score breakdown; use json, csv or table with --explain".into());
+    }
+    if args.format == Format::Prometheus && side_reports {
+        return Err(
+            "--format prometheus only holds the leaderboard and its --per-repo and --by-language groups; use json or table for the other reports"
+                .into(),
+        );
+    }
+    // The previous report has totals, not the per-PR sizes these need.
+    if args.merge_from.is_some()
+        && (args.size_scoring != SizeScoring::Linear || args.size_weighted_reviews || args.decay_half_life.is_some())
+    {
+        return Err(
+            "--merge-from only works with --size-scoring linear and without --size-weighted-reviews or --decay-half-life"
+                .into(),
+        );
+    }
+    // Nor the merge dates the weekly sparklines are drawn from.
+    if args.merge_from.is_some() && args.format == Format::TerminalChart {
+        return Err("--merge-from can't be combined with --format terminal-chart; use table".into());
+    }
+    let prior = args.merge_from.as_deref().map(PriorReport::read).transpose()?;
+    let repos = match &args.repos_file {
+        Some(path) => Some(read_repos_file(path)?),
+        None => args.repos.clone(),
+    };
+    if let (Some(since), Some(until)) = (args.since, args.until) {
+        if since > until {
+            return Err(format!("--since {} is after --until {}", since, until).into());
+        }
+    }
+    let client = http_client(Duration::from_secs(args.timeout), args.insecure, args.ca_cert.as_deref())?;
+    let tokens = match args.auth_mode {
+        AuthMode::App => {
+            let (Some(app_id), Some(installation_id), Some(private_key_path)) =
+                (&args.app_id, args.installation_id, &args.private_key_path)
+            else {
+                unreachable!("clap requires the app flags with --auth-mode app")
+            };
+            let private_key = std::fs::read(private_key_path)
+                .map_err(|e| format!("can't read {}: {}", private_key_path.display(), e))?;
+            let token = auth::installation_token(
+                &args.api_url,
+                app_id,
+                installation_id,
+                &private_key,
+                &client,
+                &args.user_agent,
+            )
+            .await?;
+            vec![token]
+        }
+        AuthMode::Token if args.token.is_empty() => match env::var("GITHUB_TOKEN") {
+            Ok(token) => vec![token],
+            Err(_) => {
+                return Err(
+                    "no GitHub token: set GITHUB_TOKEN, pass --token or GITHUB_TOKENS, or use --auth-mode app".into(),
+                )
+            }
+        },
+        AuthMode::Token => args.token.clone(),
+    };
+    let cache_dir = || {
+        args.cache_dir
+            .clone()
+            .or_else(ResponseCache::default_dir)
+            .ok_or("no cache directory: set --cache-dir")
+    };
+    let age_cutoff = opened_since(args.max_pr_age_days);
+    let mut config = FetcherConfig {
+        transport: Box::new(client),
+        max_retries: args.max_retries,
+        concurrency: args.concurrency,
+        progress: !args.no_progress && !args.quiet,
+        fail_fast: args.fail_fast,
+        options: QueryOptions {
+            comment_words: args.comment_words,
+            dismissals: args.report_dismissals,
+            merge_methods: args.merge_methods,
+            unblocking: args.credit_unblocking,
+            coauthors: args.credit_coauthors,
+            labels: !args.label.is_empty(),
+        },
+        exclude_ranges: args.exclude_range.clone(),
+        owner_type: args.owner_type,
+        states: args.states.clone(),
+        base_branch: args.base_branch.clone(),
+        opened_since: age_cutoff,
+        user_agent: args.user_agent.clone(),
+        ..FetcherConfig::default()
+    };
+    if let (Some(ttl), false) = (args.cache_ttl, args.no_cache) {
+        config.cache = Some(ResponseCache::new(&cache_dir()?, Duration::from_secs(ttl)));
+    }
+    if let (Some(ttl), false) = (args.repo_list_ttl, args.no_cache) {
+        let dir = cache_dir()?;
+        config.repository_lists = Some(RepositoryListCache::new(Duration::from_secs(ttl), Some(&dir)));
+    }
+    if let Some(pattern) = &args.repo_pattern {
+        config.repo_pattern = Some(RepoPattern::new(pattern, args.repo_pattern_case_sensitive)?);
+    }
+    let fetcher = Fetcher::new(tokens, &args.api_url, config)?;
 
-    let owner = args.owner;
-    let repos = args.repos;
-    let date = args.date;
+    let owner = match (&args.enterprise, args.owner) {
+        (Some(enterprise), _) => enterprise.clone(),
+        (None, Some(owner)) => owner,
+        (None, None) => unreachable!("clap requires --owner unless --enterprise is set"),
+    };
+    let window = DateWindow {
+        since: args.since,
+        until: args.until,
+    };
+    let mut filter = UserFilter {
+        bots: args.exclude_bots,
+        users: args.exclude_user.iter().cloned().collect(),
+        team: None,
+        outside_reviewers: args.include_outside_reviewers,
+        opened_since: age_cutoff,
+    };
+    if filter.users.contains(&args.unknown_label) {
+        filter.users.insert(DELETED_LOGIN.to_string());
+    }
+    let reviewer_weights: HashMap<String, f64> = args.reviewer_weight.iter().cloned().collect();
+    let weights = ScoringWeights {
+        approval: args.weight_approval,
+        comment: args.weight_comment,
+        review_comment: args.weight_review_comment,
+        requested_changes: args.weight_requested_changes,
+        unblocking: if args.credit_unblocking {
+            args.unblocking_weight * args.weight_approval
+        } else {
+            0.0
+        },
+        addition: args.weight_addition,
+        deletion: args.weight_deletion,
+        comment_word: args.comment_word_weight,
+        size_scoring: args.size_scoring,
+        size_weighted_reviews: args.size_weighted_reviews,
+        decay_half_life: args.decay_half_life,
+        score_mode: args.score_mode,
+    };
+    // An installation token was just minted, so only personal tokens are checked.
+    if args.auth_mode == AuthMode::Token && !args.no_auth_check {
+        let logins = fetcher.check_auth().await?;
+        for (i, login) in logins.iter().enumerate() {
+            match logins.len() {
+                1 => tracing::info!("Authenticated as {}", login),
+                _ => tracing::info!("Authenticated as {} (token #{})", login, i + 1),
+            }
+        }
+    }
 
-    println!("Fetching statistics for Owner: {}, Date: {:?}", owner, date);
+    // Names without the owner, as --repos takes them, except across an
+    // enterprise where the organization tells them apart.
+    if let Some(Command::Repos { format }) = &args.command {
+        let names: Vec<String> = match &args.enterprise {
+            Some(enterprise) => fetcher
+                .resolve_enterprise_repositories(enterprise)
+                .await?
+                .into_iter()
+                .map(|(org, repo)| format!("{}/{}", org, repo))
+                .collect(),
+            None => fetcher
+                .resolve_repositories(&owner, repos)
+                .await?
+                .into_iter()
+                .map(|(_, repo)| repo)
+                .collect(),
+        };
+        let listing = match format {
+            RepoListFormat::Plain => names.iter().map(|name| format!("{}\n", name)).collect(),
+            RepoListFormat::Json => format!("{}\n", serde_json::to_string_pretty(&names)?),
+        };
+        match &output {
+            Some(path) => write_atomic(path, listing.as_bytes())?,
+            None => print!("{}", listing),
+        }
+        return Ok(());
+    }
 
-    let client = Client::new();
-    let shared_client = Arc::new(client);
+    tracing::info!(
+        "Fetching statistics for Owner: {}, Since: {:?}, Until: {:?}",
+        owner, window.since, window.until
+    );
 
-    let repositories = match repos {
-        Some(repos) => repos,
-        None => {
-            let mut repositories =
-                get_repositories(&shared_client, &github_token, &owner, "null").await?;
-            while repositories.has_next_page() {
-                let cursor = format!("\"{}\"", repositories.next_cursor());
-                let next_page =
-                    get_repositories(&shared_client, &github_token, &owner, &cursor).await?;
-                repositories.extend(next_page);
-            }
-            repositories.repositories()
-        }
+    if args.dry_run {
+        let repositories = match &args.enterprise {
+            Some(enterprise) => fetcher.resolve_enterprise_repositories(enterprise).await?,
+            None => fetcher.resolve_repositories(&owner, repos).await?,
+        };
+        print_plan(&fetcher, &repositories, window);
+        return Ok(());
+    }
+    if let Some(team) = &args.team {
+        filter.team = Some(fetcher.team_members(&owner, team).await?);
+    }
+    let fetched = match &args.enterprise {
+        Some(enterprise) => fetcher.collect_enterprise_stats(enterprise, window).await?,
+        None => fetcher.collect_stats(&owner, repos, window).await?,
     };
-
-    let semaphore = Arc::new(tokio::sync::Semaphore::new(5));
-    let mut join_handles = JoinSet::new();
-    for (i, repo) in repositories.into_iter().enumerate() {
-        println!("Processing repo: {}", repo);
-        // if i % 5 == 0 && i != 0 {
-        //     println!("Sleeping for 10 seconds to avoid rate limiting");
-        //     tokio::time::sleep(tokio::time::Duration::from_secs(10)).await;
-        // }
-        if i % 10 == 0 && i != 0 {
-            println!("Sleeping for 10 seconds to avoid rate limiting");
-            tokio::time::sleep(tokio::time::Duration::from_secs(10)).await;
+    match fetcher.remaining() {
+        Some(remaining) => tracing::info!(
+            "Rate limit: {} points used, {} remaining",
+            fetcher.query_cost(),
+            remaining
+        ),
+        None => tracing::info!("Rate limit: {} points used", fetcher.query_cost()),
+    }
+    tracing::info!("Repositories: {}", fetched.status_summary());
+    for (repo, status) in fetched.statuses.iter() {
+        if *status == RepositoryStatus::Empty {
+            tracing::debug!("{}: no pull requests in the window", repo);
         }
-        let client = Arc::clone(&shared_client);
-        let github_token = github_token.clone();
-        let owner = owner.clone();
-        let semaphore = Arc::clone(&semaphore);
-        join_handles.spawn(async move {
-            let _permit = semaphore.acquire().await?;
-            let mut stats = get_stats(&client, &github_token, &owner, &repo, "null").await?;
-            while stats.has_next_page(date) {
-                let cursor = format!("\"{}\"", stats.next_cursor());
-                let next_resp = get_stats(&client, &github_token, &owner, &repo, &cursor).await?;
-                stats.extend(next_resp);
-            }
-            stats.trim(date);
-            Ok(stats)
-        });
     }
+    let repositories = fetched.repositories;
     let mut user_stats: GitHubUsers = GitHubUsers(HashMap::new());
-    let mut loc: u64 = 0;
     let mut prs: u64 = 0;
-    while let Some(result) = join_handles.join_next().await {
-        let handle_result: Result<RepositoryResponse> = result?;
-        let stats = handle_result?;
+    let mut additions: u64 = 0;
+    let mut deletions: u64 = 0;
+    let mut reviewers: HashSet<String> = HashSet::new();
+    let mut discussed = DiscussedPrs(Vec::new());
+    let mut by_repo: HashMap<String, GitHubUsers> = HashMap::new();
+    let mut by_language: HashMap<String, GitHubUsers> = HashMap::new();
+    let no_access: Vec<&String> = fetched
+        .statuses
+        .iter()
+        .filter(|(_, status)| *status == RepositoryStatus::AccessDenied)
+        .map(|(repo, _)| repo)
+        .collect();
+    let mut warnings = Warnings(
+        no_access
+            .into_iter()
+            .map(|repo| Warning::NoAccess { repo: repo.clone() })
+            .collect(),
+    );
+    let mut records: Vec<PullRequestRecord> = Vec::new();
+    let mut approval_latency = ApprovalLatency::new();
+    let mut cycle_time = CycleTime::new();
+    let mut merge_methods = MergeMethods::default();
+    let mut activity = WeeklyActivity::default();
+    let mut review_pairs = ReviewPairs(HashMap::new());
+    let mut date_bounds = DateBounds::new(window);
+    for (repo, stats) in repositories {
+        let Some(repository) = stats.data.repository else {
+            continue;
+        };
+        let language = repository.primary_language;
 
-        for pr in stats.data.repository.pull_requests.nodes {
-            let stats = user_stats
-                .0
-                .entry(pr.author.login)
-                .or_insert(UserStats::new());
-            stats.additions += pr.additions;
-            stats.deletions += pr.deletions;
-            stats.changed_files += pr.changed_files;
-            stats.pull_requests += 1;
+        for pr in repository.pull_requests.nodes {
+            if filter.excludes_pull_request(&pr) || (!args.label.is_empty() && !pr.has_any_label(&args.label)) {
+                continue;
+            }
+            if args.most_discussed.is_some() {
+                discussed.0.push(DiscussedPr {
+                    repo: repo.clone(),
+                    number: pr.number,
+                    title: pr.title.clone(),
+                    url: pr.url.clone(),
+                    comments: pr.discussion_volume(),
+                });
+            }
+            warnings.0.extend(pr.truncation_warnings(&repo));
+            let deleted = pr.deleted_contributions();
+            if !deleted.is_empty() {
+                tracing::debug!(
+                    "{}#{}: {} by deleted accounts, counted as {}",
+                    repo,
+                    pr.number,
+                    deleted.join(", "),
+                    args.unknown_label
+                );
+            }
+            if let Some(merged_at) = pr.merged_at {
+                date_bounds.add(merged_at);
+            }
+            if args.approval_latency {
+                approval_latency.add_pull_request(&pr);
+            }
+            if args.cycle_time {
+                cycle_time.add_pull_request(&repo, &pr);
+            }
+            if args.merge_methods && pr.merged_at.is_some() {
+                merge_methods.add(pr.merge_method());
+            }
+            if args.format == Format::TerminalChart {
+                activity.add_pull_request(&pr);
+            }
+            if args.reciprocity.is_some() {
+                review_pairs.add_pull_request(&pr);
+            }
+            if sqlite_output.is_some() {
+                records.push(PullRequestRecord::new(&repo, &pr, &args.unknown_label));
+            }
             prs += 1;
-            loc += pr.additions + pr.deletions;
-            for review in pr.reviews.nodes {
-                let stats = user_stats
-                    .0
-                    .entry(review.author.login)
-                    .or_insert(UserStats::new());
-                if review.state == "APPROVED" {
-                    stats.approvals += 1;
-                } else if review.state == "COMMENTED" {
-                    stats.comments += 1;
-                } else if review.state == "CHANGES_REQUESTED" {
-                    stats.requested_changes += 1;
-                }
+            additions += pr.additions;
+            deletions += pr.deletions;
+            reviewers.extend(
+                pr.reviews
+                    .nodes
+                    .iter()
+                    .map(|review| &review.author.login)
+                    .filter(|login| *login != DELETED_LOGIN && !filter.excludes_reviewer(login, &pr.author.login))
+                    .map(|login| login.to_lowercase()),
+            );
+            user_stats.add_pull_request(&pr, &filter, args.latest_review_only);
+            if per_repo {
+                by_repo
+                    .entry(repo.clone())
+                    .or_insert_with(|| GitHubUsers(HashMap::new()))
+                    .add_pull_request(&pr, &filter, args.latest_review_only);
             }
-
-            for comment in pr.comments.nodes {
-                let stats = user_stats
-                    .0
-                    .entry(comment.author.login)
-                    .or_insert(UserStats::new());
-                stats.comments += 1;
+            if args.by_language {
+                let language = match &language {
+                    Some(language) => language.name.clone(),
+                    None => "Unspecified".to_string(),
+                };
+                by_language
+                    .entry(language)
+                    .or_insert_with(|| GitHubUsers(HashMap::new()))
+                    .add_pull_request(&pr, &filter, args.latest_review_only);
             }
         }
     }
 
-    let scale = loc / prs; // Average LOC per PR
-    let scored = user_stats.finalize(&scale);
-    println!("{}", scored);
+    if let Some(prior) = &prior {
+        user_stats.add_prior(prior, &filter);
+        prs += prior.summary.pull_requests;
+        additions += prior.summary.additions;
+        deletions += prior.summary.deletions;
+        // The report doesn't say who reviewed, only what they did. Comments
+        // can't tell a commented review from a PR comment, so only
+        // approvals, requested changes and inline comments count.
+        reviewers.extend(
+            prior
+                .users()
+                .into_iter()
+                .filter(|(_, stats)| stats.approvals + stats.requested_changes + stats.review_comments > 0)
+                .filter(|(login, _)| !filter.excludes(login))
+                .map(|(login, _)| login.to_lowercase()),
+        );
+        if let Some(data_span) = &prior.data_span {
+            date_bounds.extend(data_span);
+        }
+    }
+
+    if prs == 0 {
+        tracing::warn!("No pull requests found for the given criteria");
+        exit_if_failed(&fetched.failures);
+        return Ok(());
+    }
+    let scale = (additions + deletions) as f64 / prs as f64; // Average LOC per PR
+    let unattributable = user_stats.take_unattributable();
+    let mut scored = user_stats.finalize(&scale, &reviewer_weights, &weights);
+    let occasional = match args.min_prs {
+        Some(min_prs) => scored.drop_occasional(min_prs),
+        None => HashSet::new(),
+    };
+    if args.normalize_score {
+        scored.normalize();
+    }
+    let mut fields = match &args.fields {
+        Some(fields) => fields.clone(),
+        None => args.format.default_fields(args.normalize_score, args.explain),
+    };
+    if args.explain && !fields.contains(&Field::Breakdown) {
+        fields.push(Field::Breakdown);
+    }
+    let mut pseudonyms = match (args.anonymize, &anonymize_key) {
+        (true, Some(key)) => Some(Pseudonyms::new(key)),
+        _ => None,
+    };
+    if let Some(output) = &sqlite_output {
+        let meta = [
+            ("owner", owner.clone()),
+            ("since", window.since.map(|d| d.to_string()).unwrap_or_default()),
+            ("until", window.until.map(|d| d.to_string()).unwrap_or_default()),
+            (
+                "earliest_merged_at",
+                date_bounds.earliest.map(|d| d.to_rfc3339()).unwrap_or_default(),
+            ),
+            (
+                "latest_merged_at",
+                date_bounds.latest.map(|d| d.to_rfc3339()).unwrap_or_default(),
+            ),
+            ("scale", scale.to_string()),
+            ("generated_at", Utc::now().to_rfc3339()),
+        ];
+        match &mut pseudonyms {
+            Some(pseudonyms) => {
+                let mut users = ScoredUser(scored.0.clone());
+                users.anonymize(pseudonyms)?;
+                for record in records.iter_mut().filter(|record| record.author != args.unknown_label) {
+                    record.author = pseudonyms.get(&record.author)?;
+                }
+                write_sqlite(output, &meta, &users, &records)?;
+            }
+            None => write_sqlite(output, &meta, &scored, &records)?,
+        }
+    }
+    // --top would cut the user out before they could be picked.
+    let top = if user.is_some() { None } else { args.top };
+    if let Some(top) = top {
+        scored.0.truncate(top);
+    }
+    // Groups are scored on the same scale as the overall ranking.
+    let rank_groups = |groups: HashMap<String, GitHubUsers>| -> BTreeMap<String, Leaderboard> {
+        groups
+            .into_iter()
+            .map(|(name, mut users)| {
+                let unattributable = users.take_unattributable();
+                let mut scored = users.finalize(&scale, &reviewer_weights, &weights);
+                scored.drop_users(&occasional);
+                if args.normalize_score {
+                    scored.normalize();
+                }
+                if let Some(top) = top {
+                    scored.0.truncate(top);
+                }
+                let leaderboard = Leaderboard {
+                    users: scored,
+                    unattributable: unattributable
+                        .map(|stats| Unattributable::new(&args.unknown_label, &stats)),
+                };
+                (name, leaderboard)
+            })
+            .collect()
+    };
+    let mut by_repo = per_repo.then(|| rank_groups(by_repo));
+    let mut by_language = args.by_language.then(|| rank_groups(by_language));
+    let mut unattributable = unattributable.map(|stats| Unattributable::new(&args.unknown_label, &stats));
+    // Scores are still relative to everyone, so they match the ranking.
+    if let Some(login) = &user {
+        scored.keep_user(login);
+        if scored.0.is_empty() {
+            tracing::warn!("{} has no contributions for the given criteria", login);
+            exit_if_failed(&fetched.failures);
+            return Ok(());
+        }
+        unattributable = None;
+        for groups in [&mut by_repo, &mut by_language].into_iter().flatten() {
+            groups.retain(|_, leaderboard| {
+                leaderboard.users.keep_user(login);
+                leaderboard.unattributable = None;
+                !leaderboard.users.0.is_empty()
+            });
+        }
+    }
+    let mut report = Report {
+        owner: owner.clone(),
+        leaderboard: Leaderboard {
+            users: scored,
+            unattributable,
+        },
+        by_repo,
+        by_language,
+        summary: Summary {
+            pull_requests: prs,
+            additions,
+            deletions,
+            reviewers: reviewers.len() as u64,
+            avg_loc_per_pr: scale,
+        },
+        data_span: date_bounds,
+        approval_latency: args.approval_latency.then(|| approval_latency.report()),
+        cycle_time: args.cycle_time.then(|| cycle_time.report()),
+        merge_methods: args.merge_methods.then_some(merge_methods),
+        reciprocity: args.reciprocity.map(|n| review_pairs.most_imbalanced(n)),
+        most_discussed: args.most_discussed.map(|n| discussed.top(n)),
+        activity: (args.format == Format::TerminalChart).then_some(activity),
+        warnings,
+    };
+    if let Some(pseudonyms) = &mut pseudonyms {
+        report.anonymize(pseudonyms)?;
+        if let Some(path) = &args.anonymize_map {
+            pseudonyms.write_map(path)?;
+        }
+    }
+    if matches!(args.format, Format::Csv | Format::JsonLines | Format::Prometheus) {
+        eprint!("{}", report.warnings);
+    }
+    if let Some(dir) = &args.output_dir {
+        write_csv_dir(&report, &fields, dir)?;
+    } else if args.format == Format::JsonLines && output.is_none() {
+        // A reader such as `head` may stop early; the lines it took are fine.
+        if let Err(e) = write_json_lines(&report, &fields, &mut io::stdout().lock()) {
+            if e.downcast_ref::<io::Error>().is_none_or(|e| e.kind() != io::ErrorKind::BrokenPipe) {
+                return Err(e.into());
+            }
+        }
+    } else {
+        let rendered = render(&report, args.format, &fields, args.json_shape, !args.no_group_digits)?;
+        match &output {
+            Some(path) => write_atomic(path, rendered.as_bytes())?,
+            None => print!("{}", rendered),
+        }
+    }
+    exit_if_failed(&fetched.failures);
 
     Ok(())
 }
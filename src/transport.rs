@@ -0,0 +1,105 @@
+use futures::future::BoxFuture;
+use reqwest::{header::HeaderMap, Client, StatusCode, Url};
+use std::{
+    fmt::{Display, Formatter},
+    sync::Arc,
+};
+
+// One GraphQL request, as the Fetcher hands it to its transport.
+pub struct GraphQlRequest<'a> {
+    pub url: &'a Url,
+    pub token: &'a str,
+    pub user_agent: &'a str,
+    pub query: &'a str,
+}
+
+// Whatever came back, errors included; the Fetcher decides what to retry.
+pub struct HttpResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: String,
+}
+
+// A request that got no response at all.
+#[derive(Debug)]
+pub enum TransportError {
+    // Couldn't connect, or the request couldn't be sent. Worth retrying.
+    Connect(String),
+    // No complete response within the client's timeout. Worth retrying.
+    Timeout,
+    Other(String),
+}
+
+impl TransportError {
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, TransportError::Connect(_) | TransportError::Timeout)
+    }
+}
+
+impl Display for TransportError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransportError::Connect(message) | TransportError::Other(message) => write!(f, "{}", message),
+            TransportError::Timeout => write!(f, "request timed out"),
+        }
+    }
+}
+
+impl std::error::Error for TransportError {}
+
+// reqwest's own message only names the URL; the cause, such as a refused
+// connection, is further down its source chain.
+impl From<reqwest::Error> for TransportError {
+    fn from(error: reqwest::Error) -> TransportError {
+        let mut message = error.to_string();
+        let mut source = std::error::Error::source(&error);
+        while let Some(cause) = source {
+            message.push_str(&format!(": {}", cause));
+            source = cause.source();
+        }
+        if error.is_timeout() {
+            TransportError::Timeout
+        } else if error.is_connect() || error.is_request() {
+            TransportError::Connect(message)
+        } else {
+            TransportError::Other(message)
+        }
+    }
+}
+
+// Sends the Fetcher's GraphQL requests. reqwest's Client is the real one;
+// tests can answer from fixtures or, with the fault-injection feature, make
+// requests fail on purpose.
+pub trait Transport: Send + Sync {
+    fn post<'a>(&'a self, request: GraphQlRequest<'a>) -> BoxFuture<'a, Result<HttpResponse, TransportError>>;
+}
+
+// The body is read as part of the request, so the client's timeout covers a
+// download that stalls halfway as well as a hung connection.
+impl Transport for Client {
+    fn post<'a>(&'a self, request: GraphQlRequest<'a>) -> BoxFuture<'a, Result<HttpResponse, TransportError>> {
+        Box::pin(async move {
+            let response = Client::post(self, request.url.clone())
+                .header("Authorization", format!("Bearer {}", request.token))
+                .header("User-Agent", request.user_agent)
+                .json(&serde_json::json!({ "query": request.query }))
+                .send()
+                .await?;
+            let status = response.status();
+            let headers = response.headers().clone();
+            Ok(HttpResponse {
+                status,
+                headers,
+                body: response.text().await?,
+            })
+        })
+    }
+}
+
+// So the caller can keep a handle on a transport the Fetcher owns, e.g. to
+// read a test transport's counters afterwards.
+impl<T: Transport + ?Sized> Transport for Arc<T> {
+    fn post<'a>(&'a self, request: GraphQlRequest<'a>) -> BoxFuture<'a, Result<HttpResponse, TransportError>> {
+        (**self).post(request)
+    }
+}
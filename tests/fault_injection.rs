@@ -0,0 +1,153 @@
+// Runs a fetch against canned GitHub responses twice, once as is and once
+// with a third of the requests failing, and checks the retries make up for
+// every fault. Needs the fault-injection feature:
+//
+//     cargo test --features fault-injection --test fault_injection
+
+use futures::future::BoxFuture;
+use git_stats::{
+    fault::FaultyTransport,
+    transport::{GraphQlRequest, HttpResponse, Transport, TransportError},
+    DateWindow, Fetcher, FetcherConfig, GitHubUsers, ScoringWeights, UserFilter,
+};
+use reqwest::{header::HeaderMap, StatusCode};
+use serde_json::json;
+use std::{collections::HashMap, sync::Arc};
+
+const REPOS: [&str; 3] = ["api", "web", "docs"];
+const PULL_REQUESTS: u64 = 150;
+const PAGE: u64 = 100;
+const LOGINS: [&str; 4] = ["alice", "bob", "carol", "dave"];
+
+// Answers the pull request query from generated data: 150 pull requests
+// per repository, newest first, 100 to a page.
+struct Fixture;
+
+impl Fixture {
+    fn page(repo: &str, after: Option<u64>) -> serde_json::Value {
+        let start = after.unwrap_or(0);
+        let end = (start + PAGE).min(PULL_REQUESTS);
+        let seed = repo.len() as u64;
+        let nodes: Vec<_> = (start..end)
+            .map(|i| {
+                let number = PULL_REQUESTS - i;
+                let author = LOGINS[((number + seed) % 4) as usize];
+                let reviewer = LOGINS[((number + seed + 1) % 4) as usize];
+                let day = 1 + number % 28;
+                json!({
+                    "number": number,
+                    "title": format!("Change {}", number),
+                    "url": format!("https://github.com/acme/{}/pull/{}", repo, number),
+                    "createdAt": format!("2024-02-{:02}T09:00:00Z", day),
+                    "updatedAt": format!("2024-03-{:02}T09:00:00Z", day),
+                    "mergedAt": format!("2024-03-{:02}T09:00:00Z", day),
+                    "additions": number * 3 + seed,
+                    "deletions": number % 7,
+                    "changedFiles": 1 + number % 5,
+                    "author": { "login": author },
+                    "reviews": {
+                        "totalCount": 1,
+                        "nodes": [{
+                            "author": { "login": reviewer },
+                            "state": if number.is_multiple_of(3) { "CHANGES_REQUESTED" } else { "APPROVED" },
+                            "submittedAt": format!("2024-03-{:02}T08:00:00Z", day),
+                            "comments": { "totalCount": number % 4 },
+                        }],
+                    },
+                    "comments": {
+                        "totalCount": 1,
+                        "nodes": [{ "author": { "login": reviewer } }],
+                    },
+                })
+            })
+            .collect();
+        json!({
+            "data": {
+                "rateLimit": { "cost": 1, "remaining": 4999, "resetAt": "2030-01-01T00:00:00Z" },
+                "repository": {
+                    "primaryLanguage": { "name": "Rust" },
+                    "pullRequests": {
+                        "nodes": nodes,
+                        "pageInfo": { "endCursor": end.to_string(), "hasNextPage": end < PULL_REQUESTS },
+                        "totalCount": PULL_REQUESTS,
+                    },
+                },
+            },
+        })
+    }
+}
+
+impl Transport for Fixture {
+    fn post<'a>(&'a self, request: GraphQlRequest<'a>) -> BoxFuture<'a, Result<HttpResponse, TransportError>> {
+        Box::pin(async move {
+            let repo = REPOS
+                .iter()
+                .find(|repo| request.query.contains(&format!("name: \"{}\"", repo)))
+                .expect("a query for one of the fixture repositories");
+            let after = request
+                .query
+                .split_once("after: \"")
+                .and_then(|(_, rest)| rest.split('"').next()?.parse().ok());
+            Ok(HttpResponse {
+                status: StatusCode::OK,
+                headers: HeaderMap::new(),
+                body: Fixture::page(repo, after).to_string(),
+            })
+        })
+    }
+}
+
+// The leaderboard and totals, as JSON so the comparison covers every field.
+async fn aggregate(transport: Box<dyn Transport>, max_retries: u32) -> serde_json::Value {
+    let config = FetcherConfig {
+        transport,
+        max_retries,
+        concurrency: 3,
+        ..FetcherConfig::default()
+    };
+    let fetcher = Fetcher::new(vec!["token".to_string()], "https://api.github.com", config).unwrap();
+    let repos = REPOS.iter().map(|repo| repo.to_string()).collect();
+    let fetched = fetcher.collect_stats("acme", Some(repos), DateWindow::default()).await.unwrap();
+    assert!(fetched.failures.is_empty(), "failed: {:?}", fetched.failures);
+    let mut users = GitHubUsers(HashMap::new());
+    let (mut prs, mut lines) = (0u64, 0u64);
+    for stats in fetched.repositories.values() {
+        for pr in stats.pull_requests().iter().flat_map(|pull_requests| &pull_requests.nodes) {
+            prs += 1;
+            lines += pr.additions + pr.deletions;
+            users.add_pull_request(pr, &UserFilter::default(), false);
+        }
+    }
+    let scale = lines as f64 / prs as f64;
+    let scored = users.finalize(&scale, &HashMap::new(), &ScoringWeights::default());
+    json!({ "pull_requests": prs, "lines": lines, "users": serde_json::to_value(&scored.0).unwrap() })
+}
+
+// Paused time skips the backoff and Retry-After sleeps.
+#[tokio::test(start_paused = true)]
+async fn faults_are_retried_until_the_aggregates_match() {
+    let baseline = aggregate(Box::new(Fixture), 0).await;
+    assert_eq!(baseline["pull_requests"], json!(PULL_REQUESTS * REPOS.len() as u64));
+
+    let faulty = Arc::new(FaultyTransport::new(Box::new(Fixture), 0.3, 2024).with_retry_after(1));
+    let result = aggregate(Box::new(faulty.clone()), 30).await;
+    assert!(faulty.injected() > 0, "no faults were injected");
+    assert_eq!(result, baseline);
+}
+
+#[tokio::test(start_paused = true)]
+async fn too_few_retries_report_the_repository_as_failed() {
+    let faulty = FaultyTransport::new(Box::new(Fixture), 1.0, 7);
+    let config = FetcherConfig {
+        transport: Box::new(faulty),
+        max_retries: 2,
+        ..FetcherConfig::default()
+    };
+    let fetcher = Fetcher::new(vec!["token".to_string()], "https://api.github.com", config).unwrap();
+    let fetched = fetcher
+        .collect_stats("acme", Some(vec!["api".to_string()]), DateWindow::default())
+        .await
+        .unwrap();
+    assert_eq!(fetched.failures.len(), 1);
+    assert!(fetched.repositories.is_empty());
+}